@@ -0,0 +1,22 @@
+-- The schema the SQLite migrations build up step by step, in one go.
+CREATE TABLE users (
+    id BIGSERIAL PRIMARY KEY,
+    username TEXT NOT NULL,
+    username_skeleton TEXT NOT NULL,
+    email TEXT NOT NULL,
+    email_canonical TEXT NOT NULL,
+    password_hash TEXT,
+    email_verified_at TIMESTAMPTZ,
+    -- same format as SQLite's CURRENT_TIMESTAMP
+    created_at TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'),
+    CONSTRAINT users_username_key UNIQUE (username),
+    CONSTRAINT users_username_skeleton_key UNIQUE (username_skeleton),
+    CONSTRAINT users_email_key UNIQUE (email),
+    CONSTRAINT users_email_canonical_key UNIQUE (email_canonical)
+);
+
+CREATE TABLE refresh_tokens (
+    token_hash TEXT PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    expires_at BIGINT NOT NULL
+);
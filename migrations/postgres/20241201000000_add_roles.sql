@@ -0,0 +1,9 @@
+-- Existing users become plain users, see `access::Role`.
+ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'user';
+
+-- Permissions granted on top of the user's role.
+CREATE TABLE user_permissions (
+    user_id BIGINT NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    permission TEXT NOT NULL,
+    PRIMARY KEY (user_id, permission)
+);
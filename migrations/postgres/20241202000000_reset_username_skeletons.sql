@@ -0,0 +1,4 @@
+-- Skeletons used to be lowercased; `repository::postgres::backfill_skeletons`
+-- recomputes them with case kept. Rows that look alike another user keep NULL, as in SQLite.
+ALTER TABLE users ALTER COLUMN username_skeleton DROP NOT NULL;
+UPDATE users SET username_skeleton = NULL;
@@ -0,0 +1,2 @@
+-- Users created before passwords existed keep a NULL hash and cannot log in.
+ALTER TABLE users ADD COLUMN password_hash TEXT;
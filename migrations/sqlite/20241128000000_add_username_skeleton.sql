@@ -0,0 +1,4 @@
+-- Filled in by the application for existing rows, see
+-- `repository::sqlite::backfill_skeletons`.
+ALTER TABLE users ADD COLUMN username_skeleton TEXT;
+CREATE UNIQUE INDEX users_username_skeleton ON users (username_skeleton);
@@ -0,0 +1,4 @@
+-- Filled in by the application for existing rows, see
+-- `repository::sqlite::backfill_canonical_emails`.
+ALTER TABLE users ADD COLUMN email_canonical TEXT;
+CREATE UNIQUE INDEX users_email_canonical ON users (email_canonical);
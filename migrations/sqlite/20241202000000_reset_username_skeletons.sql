@@ -0,0 +1,3 @@
+-- Skeletons used to be lowercased; `repository::sqlite::backfill_skeletons`
+-- recomputes them with case kept.
+UPDATE users SET username_skeleton = NULL;
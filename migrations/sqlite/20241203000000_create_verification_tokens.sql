@@ -0,0 +1,6 @@
+-- The latest verification link of each user; it is deleted when used, so
+-- every link works once.
+CREATE TABLE verification_tokens (
+    user_id INTEGER PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL UNIQUE
+);
@@ -0,0 +1,455 @@
+use std::collections::BTreeSet;
+use std::fmt;
+use std::str::FromStr;
+
+use axum::extract::rejection::{JsonRejection, PathRejection};
+use axum::extract::{FromRequestParts, Path, RawPathParams, Request, State};
+use axum::http::request::Parts;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::error::{ApiError, ErrorBody};
+use crate::repository::UserRepository;
+use crate::token::AuthUser;
+use crate::AppState;
+
+#[derive(Debug, Error)]
+#[error("unknown {kind} `{value}`")]
+pub struct ParseAccessError {
+    kind: &'static str,
+    value: String,
+}
+
+/// Why the users in `auth.admin_ids` could not be made admins.
+#[derive(Debug, Error)]
+pub enum BootstrapError {
+    #[error("user {0} does not exist")]
+    UnknownUser(i64),
+    #[error("user {0} has not verified their email")]
+    Unverified(i64),
+    #[error(transparent)]
+    Repository(#[from] ApiError),
+}
+
+/// Something a user may do to other users; users can always read, update
+/// and delete themselves.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, ToSchema,
+)]
+pub enum Permission {
+    #[serde(rename = "users:read")]
+    ReadUsers,
+    #[serde(rename = "users:list")]
+    ListUsers,
+    #[serde(rename = "users:update")]
+    UpdateUsers,
+    #[serde(rename = "users:delete")]
+    DeleteUsers,
+    /// Change roles and permissions, including one's own.
+    #[serde(rename = "roles:manage")]
+    ManageRoles,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 5] = [
+        Permission::ReadUsers,
+        Permission::ListUsers,
+        Permission::UpdateUsers,
+        Permission::DeleteUsers,
+        Permission::ManageRoles,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Permission::ReadUsers => "users:read",
+            Permission::ListUsers => "users:list",
+            Permission::UpdateUsers => "users:update",
+            Permission::DeleteUsers => "users:delete",
+            Permission::ManageRoles => "roles:manage",
+        }
+    }
+}
+
+impl FromStr for Permission {
+    type Err = ParseAccessError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|permission| permission.as_str() == value)
+            .ok_or_else(|| ParseAccessError {
+                kind: "permission",
+                value: value.to_string(),
+            })
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Every permission.
+    Admin,
+    /// Reads and updates users.
+    Moderator,
+    /// Only manages themselves.
+    #[default]
+    User,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Moderator => "moderator",
+            Role::User => "user",
+        }
+    }
+
+    /// The permissions everyone with this role has.
+    pub fn permissions(self) -> &'static [Permission] {
+        match self {
+            Role::Admin => &Permission::ALL,
+            Role::Moderator => &[Permission::ReadUsers, Permission::UpdateUsers],
+            Role::User => &[],
+        }
+    }
+
+    /// Whether this role is above `other`; nobody may change users above them.
+    pub fn outranks(self, other: Role) -> bool {
+        let rank = |role| match role {
+            Role::Admin => 2,
+            Role::Moderator => 1,
+            Role::User => 0,
+        };
+        rank(self) > rank(other)
+    }
+}
+
+impl FromStr for Role {
+    type Err = ParseAccessError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        [Role::Admin, Role::Moderator, Role::User]
+            .into_iter()
+            .find(|role| role.as_str() == value)
+            .ok_or_else(|| ParseAccessError {
+                kind: "role",
+                value: value.to_string(),
+            })
+    }
+}
+
+/// For reading the `role` column.
+impl TryFrom<String> for Role {
+    type Error = ParseAccessError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Role and extra permissions of a user.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+pub struct Access {
+    pub role: Role,
+    /// Granted on top of the permissions of the role.
+    #[serde(default)]
+    pub permissions: BTreeSet<Permission>,
+}
+
+impl Access {
+    pub fn allows(&self, permission: Permission) -> bool {
+        self.role.permissions().contains(&permission) || self.permissions.contains(&permission)
+    }
+}
+
+/// What a route requires of the caller.
+#[derive(Debug, Clone, Copy)]
+pub enum Rule {
+    Permission(Permission),
+    /// The user in the `:id` path parameter may act on themselves, everyone
+    /// else needs the permission.
+    OwnerOr(Permission),
+    /// Like [`Rule::OwnerOr`], but users with a higher role than the caller
+    /// are off limits even with the permission.
+    OwnerOrLowerRole(Permission),
+}
+
+/// State of [`authorize`], one per guarded route.
+#[derive(Clone)]
+pub struct Guard {
+    pub state: AppState,
+    pub rule: Rule,
+}
+
+/// Rejects anonymous requests with 401 and callers that lack the permission
+/// of the route's [`Rule`] with 403.
+pub async fn authorize(State(guard): State<Guard>, request: Request, next: Next) -> Response {
+    let (mut parts, body) = request.into_parts();
+    let caller = match AuthUser::from_request_parts(&mut parts, &guard.state).await {
+        Ok(caller) => caller,
+        Err(err) => return err.into_response(),
+    };
+    let (permission, target) = match guard.rule {
+        Rule::Permission(permission) => (permission, None),
+        Rule::OwnerOr(permission) | Rule::OwnerOrLowerRole(permission) => {
+            let target = path_id(&mut parts).await;
+            if target == Some(caller.id) {
+                return next.run(Request::from_parts(parts, body)).await;
+            }
+            (permission, target)
+        }
+    };
+    let access = match guard.state.users.access(caller.id).await {
+        Ok(access) => access,
+        // the token outlived its user
+        Err(ApiError::NotFound) => {
+            return ApiError::Unauthorized("User no longer exists".to_string()).into_response()
+        }
+        Err(err) => return err.into_response(),
+    };
+    if !access.allows(permission) {
+        tracing::info!(
+            user = caller.id,
+            role = %access.role,
+            %permission,
+            "permission denied"
+        );
+        return ApiError::PermissionDenied(permission).into_response();
+    }
+    if let (Rule::OwnerOrLowerRole(_), Some(target)) = (guard.rule, target) {
+        match guard.state.users.access(target).await {
+            Ok(other) if other.role.outranks(access.role) => {
+                tracing::info!(user = caller.id, target, "target has a higher role");
+                return ApiError::Forbidden("User has a higher role".to_string()).into_response();
+            }
+            // a missing user is reported by the handler
+            Ok(_) | Err(ApiError::NotFound) => {}
+            Err(err) => return err.into_response(),
+        }
+    }
+    next.run(Request::from_parts(parts, body)).await
+}
+
+async fn path_id(parts: &mut Parts) -> Option<i64> {
+    let params = RawPathParams::from_request_parts(parts, &()).await.ok()?;
+    let (_, id) = params.iter().find(|(key, _)| *key == "id")?;
+    id.parse().ok()
+}
+
+/// Makes the users in `auth.admin_ids` admins while there is no admin yet;
+/// from then on admins hand out roles through the API and the setting is
+/// ignored, so removing an id later demotes nobody.
+pub async fn bootstrap_admins(
+    users: &dyn UserRepository,
+    ids: &[i64],
+) -> Result<(), BootstrapError> {
+    if ids.is_empty() {
+        return Ok(());
+    }
+    if users.count_admins().await? > 0 {
+        tracing::info!("admins exist, ignoring auth.admin_ids");
+        return Ok(());
+    }
+    for &id in ids {
+        let user = match users.get(id).await {
+            Ok(user) => user,
+            Err(ApiError::NotFound) => return Err(BootstrapError::UnknownUser(id)),
+            Err(err) => return Err(err.into()),
+        };
+        // the id could belong to someone else after a database reset
+        if !user.email_verified {
+            return Err(BootstrapError::Unverified(id));
+        }
+    }
+    for &id in ids {
+        let mut access = users.access(id).await?;
+        access.role = Role::Admin;
+        users.set_access(id, &access).await?;
+        tracing::info!(user = id, "promoted to admin");
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/users/{id}/access",
+    tag = "users",
+    security(("bearer" = [])),
+    params(("id" = i64, Path, description = "User id")),
+    responses(
+        (status = OK, body = Access),
+        (status = UNAUTHORIZED, description = "Missing or invalid access token", body = ErrorBody),
+        (status = FORBIDDEN, description = "`users:read` is needed for other users", body = ErrorBody),
+        (status = NOT_FOUND, body = ErrorBody),
+    )
+)]
+pub async fn get_access(
+    State(state): State<AppState>,
+    id: Result<Path<i64>, PathRejection>,
+) -> Result<Json<Access>, ApiError> {
+    let Path(id) = id?;
+    Ok(Json(state.users.access(id).await?))
+}
+
+/// Replaces role and extra permissions of a user.
+///
+/// Callers can neither change users with a higher role nor grant a higher
+/// role or permissions they lack themselves, and the last admin stays an admin.
+#[utoipa::path(
+    put,
+    path = "/v1/users/{id}/access",
+    tag = "users",
+    security(("bearer" = [])),
+    params(("id" = i64, Path, description = "User id")),
+    request_body = Access,
+    responses(
+        (status = OK, body = Access),
+        (status = UNAUTHORIZED, description = "Missing or invalid access token", body = ErrorBody),
+        (status = FORBIDDEN, description = "Missing `roles:manage` or a granted permission, a role above the caller's or the last admin", body = ErrorBody),
+        (status = NOT_FOUND, body = ErrorBody),
+    )
+)]
+pub async fn update_access(
+    State(state): State<AppState>,
+    caller: AuthUser,
+    id: Result<Path<i64>, PathRejection>,
+    access: Result<Json<Access>, JsonRejection>,
+) -> Result<Json<Access>, ApiError> {
+    let Path(id) = id?;
+    let Json(access) = access?;
+    let own = state.users.access(caller.id).await?;
+    let current = state.users.access(id).await?.role;
+    if current.outranks(own.role) || access.role.outranks(own.role) {
+        return Err(ApiError::Forbidden(
+            "Cannot change or grant a role above your own".to_string(),
+        ));
+    }
+    // extra permissions must not get around the role hierarchy either
+    if let Some(&permission) = access.permissions.iter().find(|p| !own.allows(**p)) {
+        return Err(ApiError::PermissionDenied(permission));
+    }
+    state
+        .users
+        .set_access_unless_last_admin(id, &access)
+        .await?;
+    tracing::info!(user = id, by = caller.id, role = %access.role, "access changed");
+    Ok(Json(access))
+}
+
+#[cfg(test)]
+mod test_access {
+    use super::*;
+    use crate::repository::MemoryUserRepository;
+    use crate::{Email, UserName};
+
+    #[test]
+    fn test_parse() {
+        for permission in Permission::ALL {
+            assert_eq!(
+                permission.as_str().parse::<Permission>().unwrap(),
+                permission
+            );
+        }
+        assert_eq!(
+            serde_json::to_string(&Permission::ManageRoles).unwrap(),
+            r#""roles:manage""#
+        );
+        assert_eq!(
+            Role::try_from("moderator".to_string()).unwrap(),
+            Role::Moderator
+        );
+        assert_eq!(
+            "superuser".parse::<Role>().unwrap_err().to_string(),
+            "unknown role `superuser`"
+        );
+    }
+
+    #[test]
+    fn test_allows() {
+        let user = Access::default();
+        assert!(!user.allows(Permission::ListUsers));
+        let moderator = Access {
+            role: Role::Moderator,
+            ..Access::default()
+        };
+        assert!(moderator.allows(Permission::UpdateUsers));
+        assert!(!moderator.allows(Permission::DeleteUsers));
+        let granted = Access {
+            role: Role::User,
+            permissions: BTreeSet::from([Permission::DeleteUsers]),
+        };
+        assert!(granted.allows(Permission::DeleteUsers));
+        assert!(!granted.allows(Permission::ReadUsers));
+        let admin = Access {
+            role: Role::Admin,
+            ..Access::default()
+        };
+        assert!(Permission::ALL.into_iter().all(|p| admin.allows(p)));
+        assert!(!moderator.allows(Permission::ListUsers));
+    }
+
+    #[test]
+    fn test_outranks() {
+        assert!(Role::Admin.outranks(Role::Moderator));
+        assert!(Role::Moderator.outranks(Role::User));
+        assert!(!Role::Moderator.outranks(Role::Moderator));
+        assert!(!Role::User.outranks(Role::Admin));
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_admins() {
+        let users = MemoryUserRepository::default();
+        let mut ids = Vec::new();
+        for name in ["HelloWorldIAmTim", "HelloWorldIAmTom"] {
+            let email = format!("{}@example.com", name.to_lowercase());
+            let user = users
+                .create(
+                    &UserName::try_new(name.to_string()).unwrap(),
+                    &Email::try_new(email).unwrap(),
+                    "hash",
+                )
+                .await
+                .unwrap();
+            ids.push(user.id);
+        }
+        let (tim, tom) = (ids[0], ids[1]);
+        users
+            .mark_email_verified(tim, "helloworldiamtim@example.com")
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            bootstrap_admins(&users, &[tim + 10]).await,
+            Err(BootstrapError::UnknownUser(_))
+        ));
+        assert!(matches!(
+            bootstrap_admins(&users, &[tim, tom]).await,
+            Err(BootstrapError::Unverified(id)) if id == tom
+        ));
+        // all or nothing
+        assert_eq!(users.count_admins().await.unwrap(), 0);
+
+        bootstrap_admins(&users, &[tim]).await.unwrap();
+        assert_eq!(users.access(tim).await.unwrap().role, Role::Admin);
+        // once there is an admin, the setting no longer matters
+        bootstrap_admins(&users, &[tom, tom + 10]).await.unwrap();
+        assert_eq!(users.access(tom).await.unwrap().role, Role::User);
+    }
+}
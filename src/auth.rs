@@ -0,0 +1,165 @@
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::State;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::domain::normalize_username;
+use crate::error::{ApiError, ErrorBody};
+use crate::token::{hash_token, random_token, unix_now};
+use crate::{AppState, Password};
+
+/// Hash verified when the user does not exist, so that unknown usernames
+/// take as long to reject as wrong passwords.
+const DUMMY_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$c29tZXNhbHRzb21lc2FsdA$Zf0ZYjLgLIVhgNmXaxL8OLxJKG2u7F4rB5bTSVC0vH0";
+
+#[derive(Deserialize, ToSchema)]
+pub struct Login {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct Refresh {
+    refresh_token: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TokenResponse {
+    access_token: String,
+    #[schema(example = "Bearer")]
+    token_type: &'static str,
+    /// Lifetime of the access token in seconds.
+    expires_in: u64,
+    refresh_token: String,
+}
+
+/// Hashes `password` with Argon2id and a random salt, returning the PHC string.
+pub async fn hash_password(password: Password) -> Result<String, ApiError> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.get().as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|err| ApiError::Internal(err.to_string()))
+    })
+    .await
+    .map_err(|err| ApiError::Internal(err.to_string()))?
+}
+
+/// Checks `password` against a PHC hash string; malformed hashes never match.
+pub async fn verify_password(password: String, hash: String) -> Result<bool, ApiError> {
+    tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&hash).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    })
+    .await
+    .map_err(|err| ApiError::Internal(err.to_string()))
+}
+
+#[utoipa::path(
+    post,
+    path = "/v1/auth/login",
+    tag = "auth",
+    request_body = Login,
+    responses(
+        (status = OK, body = TokenResponse),
+        (status = UNAUTHORIZED, description = "Wrong username or password", body = ErrorBody),
+    )
+)]
+pub async fn login(
+    State(state): State<AppState>,
+    login: Result<Json<Login>, JsonRejection>,
+) -> Result<Json<TokenResponse>, ApiError> {
+    let Json(login) = login?;
+    let username = normalize_username(&login.username);
+    let stored = state.users.password_hash(&username).await?;
+    let (id, hash) = match stored {
+        Some((id, Some(hash))) => (Some(id), hash),
+        _ => (None, DUMMY_HASH.to_string()),
+    };
+    let valid = verify_password(login.password, hash).await?;
+    match id {
+        Some(id) if valid => Ok(Json(issue_tokens(&state, id).await?)),
+        _ => Err(ApiError::InvalidCredentials),
+    }
+}
+
+/// Exchanges a refresh token for a new token pair; the old refresh token is revoked.
+#[utoipa::path(
+    post,
+    path = "/v1/auth/refresh",
+    tag = "auth",
+    request_body = Refresh,
+    responses(
+        (status = OK, body = TokenResponse),
+        (status = UNAUTHORIZED, description = "Invalid or expired refresh token", body = ErrorBody),
+    )
+)]
+pub async fn refresh(
+    State(state): State<AppState>,
+    refresh: Result<Json<Refresh>, JsonRejection>,
+) -> Result<Json<TokenResponse>, ApiError> {
+    let Json(refresh) = refresh?;
+    let token_hash = hash_token(&refresh.refresh_token);
+    match state.users.take_refresh_token(&token_hash).await? {
+        Some((user_id, expires_at)) if expires_at > unix_now() as i64 => {
+            Ok(Json(issue_tokens(&state, user_id).await?))
+        }
+        _ => Err(ApiError::Unauthorized(
+            "Invalid or expired refresh token".to_string(),
+        )),
+    }
+}
+
+async fn issue_tokens(state: &AppState, user_id: i64) -> Result<TokenResponse, ApiError> {
+    let refresh_token = random_token();
+    let expires_at = unix_now() + state.tokens.refresh_ttl().as_secs();
+    state
+        .users
+        .insert_refresh_token(&hash_token(&refresh_token), user_id, expires_at)
+        .await?;
+    Ok(TokenResponse {
+        access_token: state.tokens.issue(user_id)?,
+        token_type: "Bearer",
+        expires_in: state.tokens.access_ttl().as_secs(),
+        refresh_token,
+    })
+}
+
+#[cfg(test)]
+mod test_auth {
+    use super::*;
+    use crate::PasswordPolicy;
+
+    #[tokio::test]
+    async fn test_hash_and_verify() {
+        let password =
+            Password::try_new("CorrectHorse42".to_string(), &PasswordPolicy::default()).unwrap();
+        let hash = hash_password(password).await.unwrap();
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("CorrectHorse42".to_string(), hash.clone())
+            .await
+            .unwrap());
+        assert!(!verify_password("WrongHorse42".to_string(), hash)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_malformed_hash() {
+        assert!(
+            !verify_password("CorrectHorse42".to_string(), String::new())
+                .await
+                .unwrap()
+        );
+        assert!(PasswordHash::new(DUMMY_HASH).is_ok());
+    }
+}
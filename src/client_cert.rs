@@ -0,0 +1,169 @@
+use std::sync::Arc;
+
+use axum::extract::{FromRequestParts, MatchedPath, Request, State};
+use axum::http::request::Parts;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use x509_parser::certificate::X509Certificate;
+use x509_parser::prelude::FromDer;
+
+use crate::config::{route_or_alias_matches, ClientRoute};
+use crate::error::ApiError;
+
+/// The certificate a client authenticated with over mutual TLS, see
+/// `server.tls.client_ca_file`.
+///
+/// Extracting it rejects requests without a verified certificate; use
+/// `Option<ClientCert>` where the certificate is optional.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientCert {
+    /// Distinguished name in certificate order, e.g. `CN=billing, O=Pirates`.
+    pub subject: String,
+    pub common_name: Option<String>,
+}
+
+impl ClientCert {
+    /// Reads the subject of a DER encoded certificate.
+    pub fn from_der(der: &[u8]) -> Option<Self> {
+        let (_, cert) = X509Certificate::from_der(der).ok()?;
+        let subject = cert.subject();
+        let common_name = subject
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())
+            .map(str::to_string);
+        Some(Self {
+            subject: subject.to_string(),
+            common_name,
+        })
+    }
+
+    /// Whether `identity` is the full subject or the common name.
+    pub fn matches(&self, identity: &str) -> bool {
+        self.subject == identity || self.common_name.as_deref() == Some(identity)
+    }
+}
+
+#[axum::async_trait]
+impl<S> FromRequestParts<S> for ClientCert
+where
+    S: Send + Sync,
+{
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<ClientCert>()
+            .cloned()
+            .ok_or_else(|| ApiError::Forbidden("Client certificate required".to_string()))
+    }
+}
+
+/// Rejects requests to the routes in `server.tls.client_routes` with 403
+/// unless they come with one of the allowed client certificates.
+pub async fn authorize(
+    State(routes): State<Arc<Vec<ClientRoute>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(path) = request.extensions().get::<MatchedPath>() else {
+        return next.run(request).await;
+    };
+    let method = request.method().as_str();
+    let Some(rule) = routes.iter().find(|rule| {
+        route_or_alias_matches(rule.method.as_deref(), &rule.route, method, path.as_str())
+    }) else {
+        return next.run(request).await;
+    };
+    match request.extensions().get::<ClientCert>() {
+        Some(cert) if rule.subjects.iter().any(|subject| cert.matches(subject)) => {
+            next.run(request).await
+        }
+        Some(cert) => {
+            tracing::warn!("client certificate `{}` is not allowed", cert.subject);
+            ApiError::Forbidden("Client certificate not allowed".to_string()).into_response()
+        }
+        None => ApiError::Forbidden("Client certificate required".to_string()).into_response(),
+    }
+}
+
+#[cfg(test)]
+mod test_client_cert {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use axum::routing::{get, post};
+    use axum::{middleware, Extension, Router};
+    use tower::ServiceExt;
+
+    fn cert(common_name: &str) -> ClientCert {
+        let mut params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::OrganizationName, "Pirates");
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, common_name);
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        ClientCert::from_der(cert.der()).unwrap()
+    }
+
+    #[test]
+    fn test_from_der() {
+        let cert = cert("billing");
+        assert_eq!(cert.subject, "CN=billing, O=Pirates");
+        assert_eq!(cert.common_name.as_deref(), Some("billing"));
+        assert!(cert.matches("billing"));
+        assert!(cert.matches("CN=billing, O=Pirates"));
+        assert!(!cert.matches("Pirates"));
+        assert!(ClientCert::from_der(b"not a certificate").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_authorize() {
+        let routes = Arc::new(vec![ClientRoute {
+            method: Some("POST".to_string()),
+            route: "/internal".to_string(),
+            subjects: vec!["billing".to_string()],
+        }]);
+        let app = |client: Option<ClientCert>| {
+            let app = Router::new()
+                .route(
+                    "/internal",
+                    post(|cert: ClientCert| async move { cert.subject })
+                        .get(|cert: Option<ClientCert>| async move { format!("{cert:?}") }),
+                )
+                .route("/public", get(|| async { "ok" }))
+                .layer(middleware::from_fn_with_state(routes.clone(), authorize));
+            match client {
+                Some(client) => app.layer(Extension(client)),
+                None => app,
+            }
+        };
+        let status = |client: Option<ClientCert>, method: &str, uri: &str| {
+            let request = axum::http::Request::builder()
+                .method(method)
+                .uri(uri)
+                .body(Body::empty())
+                .unwrap();
+            async move { app(client).oneshot(request).await.unwrap().status() }
+        };
+        assert_eq!(
+            status(Some(cert("billing")), "POST", "/internal").await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(Some(cert("shop")), "POST", "/internal").await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            status(None, "POST", "/internal").await,
+            StatusCode::FORBIDDEN
+        );
+        // other methods and routes are not restricted
+        assert_eq!(status(None, "GET", "/internal").await, StatusCode::OK);
+        assert_eq!(status(None, "GET", "/public").await, StatusCode::OK);
+    }
+}
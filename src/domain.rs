@@ -0,0 +1,749 @@
+use email_address::EmailAddress;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::validation::ErrorCode;
+
+#[derive(Deserialize, Debug)]
+#[serde(try_from = "String")]
+pub struct Email {
+    address: String,
+    canonical: String,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum EmailError {
+    #[error("Error with validating Email")]
+    Invalid,
+    #[error("Email addresses at {0} are not allowed")]
+    DomainNotAllowed(String),
+    #[error("Email addresses at {0} are blocked")]
+    DomainDenied(String),
+    #[error("{0} is a disposable email provider")]
+    Disposable(String),
+}
+
+/// Domain rules an [`Email`] has to fulfil. A listed domain also covers its subdomains.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EmailPolicy {
+    /// If not empty, only these domains are accepted.
+    pub allowed_domains: Vec<String>,
+    pub denied_domains: Vec<String>,
+    /// File with one disposable email domain per line; `#` starts a comment.
+    pub disposable_domains_file: Option<std::path::PathBuf>,
+    #[serde(skip)]
+    pub disposable_domains: std::collections::HashSet<String>,
+}
+
+impl EmailPolicy {
+    /// Reads `disposable_domains_file`, if configured.
+    pub fn load_disposable_domains(&mut self) -> std::io::Result<()> {
+        if let Some(path) = &self.disposable_domains_file {
+            self.disposable_domains = std::fs::read_to_string(path)?
+                .lines()
+                .map(|line| line.split('#').next().unwrap_or_default().trim())
+                .filter(|domain| !domain.is_empty())
+                .map(str::to_lowercase)
+                .collect();
+        }
+        Ok(())
+    }
+}
+
+/// Whether `domain` or one of its parent domains is in `domains`.
+fn domain_listed<'a>(domain: &str, domains: impl IntoIterator<Item = &'a String>) -> bool {
+    domains.into_iter().any(|listed| {
+        let listed = listed.to_lowercase();
+        domain == listed || domain.ends_with(&format!(".{listed}"))
+    })
+}
+
+/// Providers that ignore dots and/or `+tag` suffixes in the local part.
+const PROVIDER_RULES: &[(&str, bool, bool)] = &[
+    // (domain, ignores dots, ignores +tags)
+    ("gmail.com", true, true),
+    ("googlemail.com", true, true),
+    ("outlook.com", false, true),
+    ("hotmail.com", false, true),
+    ("live.com", false, true),
+    ("icloud.com", false, true),
+    ("fastmail.com", false, true),
+    ("proton.me", false, true),
+    ("protonmail.com", false, true),
+];
+
+impl Email {
+    pub fn try_new(email: String) -> Result<Self, EmailError> {
+        Self::try_with_policy(email, &EmailPolicy::default())
+    }
+    pub fn try_with_policy(email: String, policy: &EmailPolicy) -> Result<Self, EmailError> {
+        if !EmailAddress::is_valid(&email) {
+            return Err(EmailError::Invalid);
+        }
+        let email = Self::from_stored(email);
+        let domain = email.domain();
+        if !policy.allowed_domains.is_empty() && !domain_listed(domain, &policy.allowed_domains) {
+            Err(EmailError::DomainNotAllowed(domain.to_string()))
+        } else if domain_listed(domain, &policy.denied_domains) {
+            Err(EmailError::DomainDenied(domain.to_string()))
+        } else if domain_listed(domain, &policy.disposable_domains) {
+            Err(EmailError::Disposable(domain.to_string()))
+        } else {
+            Ok(email)
+        }
+    }
+    /// Wraps an address read back from the database without validating it again.
+    pub fn from_stored(address: String) -> Self {
+        let canonical = canonicalize(&address);
+        Self { address, canonical }
+    }
+    pub fn get(&self) -> &str {
+        &self.address
+    }
+    /// The form used to detect duplicates: lowercased, and for known providers
+    /// without dots or `+tag` suffixes, so `F.oo+news@GMail.com` is `foo@gmail.com`.
+    pub fn canonical(&self) -> &str {
+        &self.canonical
+    }
+    fn domain(&self) -> &str {
+        self.canonical
+            .rsplit_once('@')
+            .map_or("", |(_, domain)| domain)
+    }
+}
+
+fn canonicalize(address: &str) -> String {
+    let address = address.to_lowercase();
+    let Some((local, domain)) = address.rsplit_once('@') else {
+        return address;
+    };
+    let mut local = local.to_string();
+    let mut domain = domain.to_string();
+    if let Some(&(_, ignores_dots, ignores_tags)) = PROVIDER_RULES
+        .iter()
+        .find(|(provider, _, _)| *provider == domain)
+    {
+        if ignores_tags {
+            local.truncate(local.find('+').unwrap_or(local.len()));
+        }
+        if ignores_dots {
+            local.retain(|c| c != '.');
+        }
+        if domain == "googlemail.com" {
+            domain = "gmail.com".to_string();
+        }
+    }
+    format!("{local}@{domain}")
+}
+
+impl ErrorCode for EmailError {
+    fn code(&self) -> &'static str {
+        match self {
+            EmailError::Invalid => "invalid_email",
+            EmailError::DomainNotAllowed(_) => "domain_not_allowed",
+            EmailError::DomainDenied(_) => "domain_denied",
+            EmailError::Disposable(_) => "disposable_domain",
+        }
+    }
+}
+
+impl TryFrom<String> for Email {
+    type Error = EmailError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Email::try_new(value)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(try_from = "String")]
+pub struct UserName(String);
+
+/// A class of characters a [`UserNamePolicy`] can allow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CharClass {
+    Letter,
+    Digit,
+    Whitespace,
+    Punctuation,
+}
+
+impl CharClass {
+    fn contains(self, c: char) -> bool {
+        match self {
+            CharClass::Letter => c.is_alphabetic(),
+            CharClass::Digit => c.is_numeric(),
+            CharClass::Whitespace => c.is_whitespace(),
+            CharClass::Punctuation => c.is_ascii_punctuation(),
+        }
+    }
+}
+
+/// A regular expression a whole [`UserName`] has to match, configured as a string.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct UserNamePattern(regex::Regex);
+
+impl TryFrom<String> for UserNamePattern {
+    type Error = regex::Error;
+
+    fn try_from(pattern: String) -> Result<Self, Self::Error> {
+        regex::Regex::new(&pattern).map(Self)
+    }
+}
+
+impl From<UserNamePattern> for String {
+    fn from(pattern: UserNamePattern) -> Self {
+        pattern.0.as_str().to_string()
+    }
+}
+
+/// Rules a [`UserName`] has to fulfil. Lengths are counted in grapheme clusters.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UserNamePolicy {
+    pub min_length: usize,
+    /// Longest allowed name, inclusive. The default of 31 keeps the original
+    /// rule that names have to be shorter than 32.
+    pub max_length: usize,
+    /// Characters that are never allowed.
+    pub forbidden_characters: String,
+    /// If not empty, every character has to belong to one of these classes.
+    pub allowed_classes: Vec<CharClass>,
+    pub pattern: Option<UserNamePattern>,
+    /// Names that cannot be registered, e.g. `admin`.
+    pub reserved_names: Vec<String>,
+    /// Whether reserved names only match with the exact same case.
+    pub case_sensitive: bool,
+}
+
+impl Default for UserNamePolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 12,
+            max_length: 31,
+            forbidden_characters: "!§$%&/()=?".to_string(),
+            allowed_classes: Vec::new(),
+            pattern: None,
+            reserved_names: Vec::new(),
+            case_sensitive: true,
+        }
+    }
+}
+
+impl UserNamePolicy {
+    fn is_reserved(&self, username: &str) -> bool {
+        self.reserved_names.iter().any(|reserved| {
+            if self.case_sensitive {
+                reserved == username
+            } else {
+                reserved.to_lowercase() == username.to_lowercase()
+            }
+        })
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum UserNameError {
+    #[error("Username is too short; It needs a minimum length of {0} Characters")]
+    TooShort(usize),
+    #[error("Username is too long; The maximum Length is {0}")]
+    TooLong(usize),
+    #[error("Invalid Character {0} in Username")]
+    InvalidCharacter(String),
+    #[error("Username does not match the required pattern {0}")]
+    PatternMismatch(String),
+    #[error("Username {0} is reserved")]
+    Reserved(String),
+}
+
+impl UserName {
+    pub fn try_new(username: String) -> Result<Self, UserNameError> {
+        Self::try_with_policy(username, &UserNamePolicy::default())
+    }
+    /// Validates the NFKC normalized form of `username`, which is also what gets stored.
+    pub fn try_with_policy(
+        username: String,
+        policy: &UserNamePolicy,
+    ) -> Result<Self, UserNameError> {
+        let username = normalize_username(&username);
+        let length = username.graphemes(true).count();
+        if length < policy.min_length {
+            return Err(UserNameError::TooShort(policy.min_length));
+        } else if length > policy.max_length {
+            return Err(UserNameError::TooLong(policy.max_length));
+        }
+        for char in username.chars() {
+            let allowed = policy.allowed_classes.is_empty()
+                || policy
+                    .allowed_classes
+                    .iter()
+                    .any(|class| class.contains(char));
+            if !allowed || policy.forbidden_characters.contains(char) {
+                return Err(UserNameError::InvalidCharacter(char.to_string()));
+            }
+        }
+        if let Some(pattern) = &policy.pattern {
+            if !pattern.0.is_match(&username) {
+                return Err(UserNameError::PatternMismatch(pattern.0.to_string()));
+            }
+        }
+        if policy.is_reserved(&username) {
+            return Err(UserNameError::Reserved(username));
+        }
+        Ok(Self(username))
+    }
+    /// Wraps a name read back from the database without validating it again.
+    pub fn from_stored(username: String) -> Self {
+        Self(username)
+    }
+    pub fn get(&self) -> &str {
+        &self.0
+    }
+    /// The UTS #39 skeleton; names with equal skeletons look alike, e.g.
+    /// `HelloWorldIAmTim` and `HeIIoWor1dIAmTim`. Case is kept, so names that
+    /// only differ in case are distinct users.
+    pub fn skeleton(&self) -> String {
+        unicode_security::skeleton(&self.0).collect()
+    }
+}
+
+/// NFKC normalization, so that compatibility variants such as fullwidth
+/// letters are treated as the characters they stand for.
+pub(crate) fn normalize_username(username: &str) -> String {
+    username.nfkc().collect()
+}
+
+impl ErrorCode for UserNameError {
+    fn code(&self) -> &'static str {
+        match self {
+            UserNameError::TooShort(_) => "too_short",
+            UserNameError::TooLong(_) => "too_long",
+            UserNameError::InvalidCharacter(_) => "invalid_character",
+            UserNameError::PatternMismatch(_) => "pattern_mismatch",
+            UserNameError::Reserved(_) => "reserved",
+        }
+    }
+}
+
+impl TryFrom<String> for UserName {
+    type Error = UserNameError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        UserName::try_new(value)
+    }
+}
+
+/// A plain-text password that satisfied the [`PasswordPolicy`] it was checked against.
+///
+/// It is only ever kept in memory long enough to be hashed.
+pub struct Password(String);
+
+impl std::fmt::Debug for Password {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Password(***)")
+    }
+}
+
+/// Strength rules a [`Password`] has to fulfil.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_special: bool,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 12,
+            max_length: 128,
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_special: false,
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PasswordError {
+    #[error("Password is too short; It needs a minimum length of {0} Characters")]
+    TooShort(usize),
+    #[error("Password is too long; The maximum Length is {0}")]
+    TooLong(usize),
+    #[error("Password needs at least one lowercase letter")]
+    MissingLowercase,
+    #[error("Password needs at least one uppercase letter")]
+    MissingUppercase,
+    #[error("Password needs at least one digit")]
+    MissingDigit,
+    #[error("Password needs at least one special character")]
+    MissingSpecial,
+}
+
+impl Password {
+    pub fn try_new(password: String, policy: &PasswordPolicy) -> Result<Self, PasswordError> {
+        let length = password.chars().count();
+        if length < policy.min_length {
+            Err(PasswordError::TooShort(policy.min_length))
+        } else if length > policy.max_length {
+            Err(PasswordError::TooLong(policy.max_length))
+        } else if policy.require_lowercase && !password.chars().any(char::is_lowercase) {
+            Err(PasswordError::MissingLowercase)
+        } else if policy.require_uppercase && !password.chars().any(char::is_uppercase) {
+            Err(PasswordError::MissingUppercase)
+        } else if policy.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            Err(PasswordError::MissingDigit)
+        } else if policy.require_special && password.chars().all(char::is_alphanumeric) {
+            Err(PasswordError::MissingSpecial)
+        } else {
+            Ok(Self(password))
+        }
+    }
+    pub fn get(&self) -> &str {
+        &self.0
+    }
+}
+
+impl ErrorCode for PasswordError {
+    fn code(&self) -> &'static str {
+        match self {
+            PasswordError::TooShort(_) => "too_short",
+            PasswordError::TooLong(_) => "too_long",
+            PasswordError::MissingLowercase => "missing_lowercase",
+            PasswordError::MissingUppercase => "missing_uppercase",
+            PasswordError::MissingDigit => "missing_digit",
+            PasswordError::MissingSpecial => "missing_special",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_email {
+    use super::*;
+
+    #[test]
+    fn test_good() {
+        assert!(Email::try_new("example@s.example".to_string()).is_ok());
+        assert!(Email::try_new("admin@mailserver1".to_string()).is_ok());
+        assert!(Email::try_new("example-indeed@strange-example.com".to_string()).is_ok());
+    }
+
+    #[test]
+    fn test_bad() {
+        assert!(Email::try_new("this\\ still\"not\\allowed@example.com".to_string()).is_err());
+        assert!(Email::try_new("a\"b(c)d,e:f;g<h>i[j\\k]l@example.com".to_string()).is_err());
+        assert!(Email::try_new("A@b@c@example.com".to_string()).is_err());
+        assert!(Email::try_new("Abc.example.com".to_string()).is_err());
+        assert!(Email::try_new(
+            "1234567890123456789012345678901234567890123456789012345678901234+x@example.co"
+                .to_string()
+        )
+        .is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_email_policy {
+    use super::*;
+
+    fn canonical(email: &str) -> String {
+        Email::try_new(email.to_string())
+            .unwrap()
+            .canonical()
+            .to_string()
+    }
+
+    #[test]
+    fn test_canonical() {
+        assert_eq!(canonical("Foo@Example.com"), canonical("foo@example.com"));
+        assert_eq!(canonical("F.o.o+news@GoogleMail.com"), "foo@gmail.com");
+        assert_eq!(canonical("foo+news@outlook.com"), "foo@outlook.com");
+        assert_eq!(canonical("f.oo@outlook.com"), "f.oo@outlook.com");
+        assert_eq!(canonical("f.oo+bar@example.com"), "f.oo+bar@example.com");
+        let email = Email::try_new("Foo@Example.com".to_string()).unwrap();
+        assert_eq!(email.get(), "Foo@Example.com");
+    }
+
+    #[test]
+    fn test_domain_lists() {
+        let policy = EmailPolicy {
+            allowed_domains: vec!["example.com".to_string()],
+            denied_domains: vec!["spam.example.com".to_string()],
+            ..EmailPolicy::default()
+        };
+        let check = |email: &str| Email::try_with_policy(email.to_string(), &policy);
+        assert!(check("tim@example.com").is_ok());
+        assert!(check("tim@mail.EXAMPLE.com").is_ok());
+        assert_eq!(
+            check("tim@example.org").unwrap_err(),
+            EmailError::DomainNotAllowed("example.org".to_string())
+        );
+        assert_eq!(
+            check("tim@notexample.com").unwrap_err(),
+            EmailError::DomainNotAllowed("notexample.com".to_string())
+        );
+        assert_eq!(
+            check("tim@eu.spam.example.com").unwrap_err(),
+            EmailError::DomainDenied("eu.spam.example.com".to_string())
+        );
+    }
+
+    #[test]
+    fn test_disposable_domains() {
+        let path =
+            std::env::temp_dir().join(format!("pirate_api_disposable_{}.txt", std::process::id()));
+        std::fs::write(
+            &path,
+            "# disposable providers\nMailinator.com\n\ntrashmail.de # german\n",
+        )
+        .unwrap();
+        let mut policy = EmailPolicy {
+            disposable_domains_file: Some(path.clone()),
+            ..EmailPolicy::default()
+        };
+        policy.load_disposable_domains().unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(policy.disposable_domains.len(), 2);
+        assert_eq!(
+            Email::try_with_policy("tim@mailinator.com".to_string(), &policy).unwrap_err(),
+            EmailError::Disposable("mailinator.com".to_string())
+        );
+        assert!(Email::try_with_policy("tim@example.com".to_string(), &policy).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod test_username {
+    use super::*;
+
+    #[test]
+    fn test_good() {
+        assert!(UserName::try_new("HelloWorldIAmTim".to_string()).is_ok());
+        assert!(UserName::try_new("HelloWorld123141IAmTim".to_string()).is_ok());
+        assert!(UserName::try_new("HelloWorld.....IAmTim".to_string()).is_ok());
+    }
+
+    #[test]
+    fn test_bad() {
+        assert!(UserName::try_new("test".to_string()).is_err());
+        assert!(UserName::try_new("?testhallowkfahfla".to_string()).is_err());
+        assert!(
+            UserName::try_new("halloweltichbindertimundichhasselangeusernames".to_string())
+                .is_err()
+        );
+        assert!(UserName::try_new("test!%$/".to_string()).is_err());
+    }
+    #[test]
+    fn test_max_length() {
+        assert!(UserName::try_new("a".repeat(31)).is_ok());
+        assert_eq!(
+            UserName::try_new("a".repeat(32)).unwrap_err(),
+            UserNameError::TooLong(31)
+        );
+    }
+    #[test]
+    fn test_correct_errors() {
+        assert_eq!(
+            UserName::try_new("test".to_string()).unwrap_err(),
+            UserNameError::TooShort(12)
+        );
+
+        assert_eq!(
+            UserName::try_new("?testhallowkfahfla".to_string()).unwrap_err(),
+            UserNameError::InvalidCharacter('?'.to_string())
+        );
+
+        assert_eq!(
+            UserName::try_new("halloweltichbindertimundichhasselangeusernames".to_string())
+                .unwrap_err(),
+            UserNameError::TooLong(31)
+        );
+
+        assert_eq!(
+            UserName::try_new("test!%$/".to_string()).unwrap_err(),
+            UserNameError::TooShort(12)
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_username_policy {
+    use super::*;
+
+    fn check(username: &str, policy: &UserNamePolicy) -> Result<UserName, UserNameError> {
+        UserName::try_with_policy(username.to_string(), policy)
+    }
+
+    #[test]
+    fn test_length() {
+        let policy = UserNamePolicy {
+            min_length: 3,
+            max_length: 5,
+            ..UserNamePolicy::default()
+        };
+        assert!(check("tim", &policy).is_ok());
+        assert!(check("timmy", &policy).is_ok());
+        assert_eq!(
+            check("ti", &policy).unwrap_err(),
+            UserNameError::TooShort(3)
+        );
+        assert_eq!(
+            check("timothy", &policy).unwrap_err(),
+            UserNameError::TooLong(5)
+        );
+    }
+
+    #[test]
+    fn test_allowed_classes() {
+        let policy = UserNamePolicy {
+            allowed_classes: vec![CharClass::Letter, CharClass::Digit],
+            ..UserNamePolicy::default()
+        };
+        assert!(check("HelloWorld123", &policy).is_ok());
+        assert_eq!(
+            check("Hello World123", &policy).unwrap_err(),
+            UserNameError::InvalidCharacter(" ".to_string())
+        );
+    }
+
+    #[test]
+    fn test_pattern() {
+        let policy: UserNamePolicy =
+            serde_json::from_str(r#"{"pattern": "^[a-z][a-z0-9_]*$"}"#).unwrap();
+        assert!(check("hello_world_1", &policy).is_ok());
+        assert_eq!(
+            check("1hello_world", &policy).unwrap_err(),
+            UserNameError::PatternMismatch("^[a-z][a-z0-9_]*$".to_string())
+        );
+        assert!(serde_json::from_str::<UserNamePolicy>(r#"{"pattern": "("}"#).is_err());
+    }
+
+    #[test]
+    fn test_reserved() {
+        let mut policy = UserNamePolicy {
+            min_length: 1,
+            reserved_names: vec!["admin".to_string()],
+            ..UserNamePolicy::default()
+        };
+        assert_eq!(
+            check("admin", &policy).unwrap_err(),
+            UserNameError::Reserved("admin".to_string())
+        );
+        assert!(check("Admin", &policy).is_ok());
+
+        policy.case_sensitive = false;
+        assert_eq!(
+            check("Admin", &policy).unwrap_err(),
+            UserNameError::Reserved("Admin".to_string())
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_username_unicode {
+    use super::*;
+
+    #[test]
+    fn test_grapheme_length() {
+        // 11 characters, but 22 bytes
+        assert_eq!(
+            UserName::try_new("ÄÖÜäöüßÄÖÜä".to_string()).unwrap_err(),
+            UserNameError::TooShort(12)
+        );
+        // 30 characters, but 60 bytes
+        assert!(UserName::try_new("äöü".repeat(10)).is_ok());
+        // "e" followed by a combining acute accent is a single grapheme
+        assert_eq!(
+            UserName::try_new("e\u{301}".repeat(11)).unwrap_err(),
+            UserNameError::TooShort(12)
+        );
+    }
+
+    #[test]
+    fn test_normalization() {
+        let username = UserName::try_new("ＨｅｌｌｏＷｏｒｌｄＩＡｍＴｉｍ".to_string()).unwrap();
+        assert_eq!(username.get(), "HelloWorldIAmTim");
+        let composed = UserName::try_new("Jürgen der Pirat".to_string()).unwrap();
+        let decomposed = UserName::try_new("Ju\u{308}rgen der Pirat".to_string()).unwrap();
+        assert_eq!(composed.get(), decomposed.get());
+    }
+
+    #[test]
+    fn test_skeleton() {
+        let skeleton = |name: &str| UserName::try_new(name.to_string()).unwrap().skeleton();
+        assert_eq!(skeleton("HelloWorldIAmTim"), skeleton("HeIIoWor1dIAmTim"));
+        assert_eq!(skeleton("paypal_account"), skeleton("pa\u{443}pal_account"));
+        assert_ne!(skeleton("PayPal_Account"), skeleton("paypal_account"));
+        assert_ne!(skeleton("HelloWorldIAmTim"), skeleton("HelloWorldIAmTom"));
+    }
+}
+
+#[cfg(test)]
+mod test_password {
+    use super::*;
+
+    #[test]
+    fn test_good() {
+        let policy = PasswordPolicy::default();
+        assert!(Password::try_new("CorrectHorse42".to_string(), &policy).is_ok());
+        assert!(Password::try_new("Ünïcödé-Pässwörd1".to_string(), &policy).is_ok());
+    }
+
+    #[test]
+    fn test_correct_errors() {
+        let policy = PasswordPolicy::default();
+        assert_eq!(
+            Password::try_new("Short1".to_string(), &policy).unwrap_err(),
+            PasswordError::TooShort(12)
+        );
+        assert_eq!(
+            Password::try_new("A1".repeat(65), &policy).unwrap_err(),
+            PasswordError::TooLong(128)
+        );
+        assert_eq!(
+            Password::try_new("CORRECTHORSE42".to_string(), &policy).unwrap_err(),
+            PasswordError::MissingLowercase
+        );
+        assert_eq!(
+            Password::try_new("correcthorse42".to_string(), &policy).unwrap_err(),
+            PasswordError::MissingUppercase
+        );
+        assert_eq!(
+            Password::try_new("CorrectHorseBattery".to_string(), &policy).unwrap_err(),
+            PasswordError::MissingDigit
+        );
+    }
+
+    #[test]
+    fn test_custom_policy() {
+        let policy = PasswordPolicy {
+            min_length: 4,
+            require_uppercase: false,
+            require_digit: false,
+            require_special: true,
+            ..PasswordPolicy::default()
+        };
+        assert!(Password::try_new("abc!".to_string(), &policy).is_ok());
+        assert_eq!(
+            Password::try_new("abcd".to_string(), &policy).unwrap_err(),
+            PasswordError::MissingSpecial
+        );
+    }
+
+    #[test]
+    fn test_debug_is_redacted() {
+        let password = Password::try_new("CorrectHorse42".to_string(), &PasswordPolicy::default());
+        assert_eq!(format!("{:?}", password.unwrap()), "Password(***)");
+    }
+}
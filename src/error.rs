@@ -0,0 +1,196 @@
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
+use axum::http::header::{RETRY_AFTER, WWW_AUTHENTICATE};
+use axum::http::{HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::access::Permission;
+use crate::telemetry::current_request_id;
+use crate::validation::{FieldError, ValidationErrors};
+
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("{}", .0.body_text())]
+    InvalidJson(#[from] JsonRejection),
+    #[error("{}", .0.body_text())]
+    InvalidPath(#[from] PathRejection),
+    #[error("{}", .0.body_text())]
+    InvalidQuery(#[from] QueryRejection),
+    #[error("Request validation failed")]
+    Validation(#[from] ValidationErrors),
+    #[error("Invalid username or password")]
+    InvalidCredentials,
+    #[error("Authentication required: {0}")]
+    Unauthorized(String),
+    #[error("Access denied: {0}")]
+    Forbidden(String),
+    #[error("Access denied: missing permission `{0}`")]
+    PermissionDenied(Permission),
+    #[error("Access denied: the last admin can't be demoted or deleted")]
+    LastAdmin,
+    #[error("Invalid or expired token: {0}")]
+    InvalidToken(String),
+    #[error("User not found")]
+    NotFound,
+    #[error("A user with this {0} already exists")]
+    Conflict(String),
+    #[error("Too many requests; retry in {retry_after} seconds")]
+    RateLimited { retry_after: u64 },
+    #[error("API version {0} is not supported")]
+    UnsupportedVersion(u32),
+    #[error("Request timed out")]
+    Timeout,
+    #[error("Database error")]
+    Database(#[from] sqlx::Error),
+    #[error("Internal server error")]
+    Internal(String),
+}
+
+/// Fatal errors while starting the server, reported instead of panicking.
+#[derive(Debug, Error)]
+pub enum StartupError {
+    #[error("invalid configuration: {0}")]
+    Config(#[from] Box<figment::Error>),
+    #[error("failed to read disposable email domains: {0}")]
+    DisposableDomains(#[source] std::io::Error),
+    #[error("failed to read the docs script: {0}")]
+    DocsScript(#[source] std::io::Error),
+    #[error("invalid log level: {0}")]
+    LogLevel(#[from] tracing_subscriber::filter::ParseError),
+    #[error("failed to set up OTLP export: {0}")]
+    Otlp(#[from] opentelemetry::trace::TraceError),
+    #[error("failed to open database: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("failed to make the users in auth.admin_ids admins: {0}")]
+    Admins(#[from] crate::access::BootstrapError),
+    #[error("no database backend for `{0}:` URLs is enabled")]
+    UnsupportedDatabase(String),
+    #[error("failed to read JWT keys: {0}")]
+    KeyFile(#[source] std::io::Error),
+    #[error("invalid mail configuration: {0}")]
+    Mail(#[from] crate::mail::MailError),
+    #[error("invalid JWT keys: {0}")]
+    Keys(#[from] jsonwebtoken::errors::Error),
+    #[error("invalid HTTP configuration: {0}")]
+    Http(#[from] crate::layers::LayerError),
+    #[error("invalid TLS configuration: {0}")]
+    Tls(#[from] crate::tls::TlsError),
+    #[error("failed to bind {addr}: {source}")]
+    Bind {
+        addr: std::net::SocketAddr,
+        source: std::io::Error,
+    },
+    #[error("server error: {0}")]
+    Serve(#[source] std::io::Error),
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidJson(rejection) => rejection.status(),
+            ApiError::InvalidPath(rejection) => rejection.status(),
+            ApiError::InvalidQuery(rejection) => rejection.status(),
+            ApiError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::InvalidCredentials | ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) | ApiError::PermissionDenied(_) | ApiError::LastAdmin => {
+                StatusCode::FORBIDDEN
+            }
+            ApiError::InvalidToken(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::UnsupportedVersion(_) => StatusCode::NOT_ACCEPTABLE,
+            ApiError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            ApiError::Database(_) | ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidJson(rejection)
+                if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE =>
+            {
+                "payload_too_large"
+            }
+            ApiError::InvalidJson(_) => "invalid_json",
+            ApiError::InvalidPath(_) => "invalid_path",
+            ApiError::InvalidQuery(_) => "invalid_query",
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::InvalidCredentials => "invalid_credentials",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::LastAdmin => "last_admin",
+            ApiError::PermissionDenied(_) => "permission_denied",
+            ApiError::InvalidToken(_) => "invalid_token",
+            ApiError::NotFound => "not_found",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::RateLimited { .. } => "rate_limited",
+            ApiError::UnsupportedVersion(_) => "unsupported_version",
+            ApiError::Timeout => "timeout",
+            ApiError::Database(_) | ApiError::Internal(_) => "internal",
+        }
+    }
+}
+
+/// JSON body of every error response.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    /// Machine readable error code, e.g. `validation_failed`.
+    #[schema(example = "validation_failed")]
+    pub code: &'static str,
+    pub message: String,
+    /// The failed fields, only present for `validation_failed`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<Vec<FieldError>>,
+    /// The permission the caller lacks, only present for `permission_denied`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub required_permission: Option<Permission>,
+    /// Same as the `X-Request-Id` response header.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        match &self {
+            ApiError::Database(err) => tracing::error!("database error: {err}"),
+            ApiError::Internal(err) => tracing::error!("internal error: {err}"),
+            _ => {}
+        }
+        let body = ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+            errors: match &self {
+                ApiError::Validation(errors) => Some(errors.0.clone()),
+                _ => None,
+            },
+            required_permission: match &self {
+                ApiError::PermissionDenied(permission) => Some(*permission),
+                _ => None,
+            },
+            request_id: current_request_id(),
+        };
+        let mut response = (self.status(), Json(body)).into_response();
+        if let ApiError::Validation(errors) = &self {
+            // picked up by the metrics middleware
+            response.extensions_mut().insert(errors.clone());
+        }
+        match &self {
+            ApiError::Unauthorized(_) => {
+                response
+                    .headers_mut()
+                    .insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            ApiError::RateLimited { retry_after } => {
+                response
+                    .headers_mut()
+                    .insert(RETRY_AFTER, HeaderValue::from(*retry_after));
+            }
+            _ => {}
+        }
+        response
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::Json;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::repository::UserRepository;
+use crate::shutdown::Lifecycle;
+use crate::AppState;
+
+/// How long a dependency may take to answer before it counts as down.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Component {
+    status: Status,
+    /// Short generic reason; the details are only logged, as the endpoint is public.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "unavailable")]
+    error: Option<&'static str>,
+}
+
+impl Component {
+    fn check(result: Result<(), &'static str>) -> Self {
+        match result {
+            Ok(()) => Self {
+                status: Status::Up,
+                error: None,
+            },
+            Err(reason) => Self {
+                status: Status::Down,
+                error: Some(reason),
+            },
+        }
+    }
+}
+
+/// Overall status; `down` as soon as one component is down.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Health {
+    status: Status,
+    components: BTreeMap<&'static str, Component>,
+}
+
+impl Health {
+    fn new(components: BTreeMap<&'static str, Component>) -> Self {
+        let status = if components.values().all(|c| c.status == Status::Up) {
+            Status::Up
+        } else {
+            Status::Down
+        };
+        Self { status, components }
+    }
+
+    fn status_code(&self) -> StatusCode {
+        match self.status {
+            Status::Up => StatusCode::OK,
+            Status::Down => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Version {
+    #[schema(example = "pirate_api")]
+    name: &'static str,
+    #[schema(example = "0.1.0")]
+    version: &'static str,
+    /// Git commit the binary was built from, or `unknown`.
+    commit: &'static str,
+}
+
+/// Liveness: the process is running and serving requests.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "health",
+    responses((status = OK, body = Health))
+)]
+pub async fn healthz() -> Json<Health> {
+    Json(Health::new(BTreeMap::new()))
+}
+
+/// Readiness: the database is reachable and the server is not shutting down.
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "health",
+    responses(
+        (status = OK, body = Health),
+        (status = SERVICE_UNAVAILABLE, description = "A component is down", body = Health),
+    )
+)]
+pub async fn readyz(State(state): State<AppState>) -> (StatusCode, Json<Health>) {
+    let health = readiness(&*state.users, &state.lifecycle).await;
+    (health.status_code(), Json(health))
+}
+
+#[utoipa::path(
+    get,
+    path = "/version",
+    tag = "health",
+    responses((status = OK, body = Version))
+)]
+pub async fn version() -> Json<Version> {
+    Json(Version {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        commit: env!("GIT_COMMIT"),
+    })
+}
+
+async fn readiness(users: &dyn UserRepository, lifecycle: &Lifecycle) -> Health {
+    let database = match tokio::time::timeout(CHECK_TIMEOUT, users.ping()).await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(err)) => {
+            // `Debug`, the message of `ApiError::Database` hides the cause
+            tracing::warn!("database check failed: {err:?}");
+            Err("unavailable")
+        }
+        Err(_) => {
+            tracing::warn!("database check timed out after {CHECK_TIMEOUT:?}");
+            Err("timed out")
+        }
+    };
+    let server = if lifecycle.is_ready() {
+        Ok(())
+    } else {
+        Err("shutting down")
+    };
+    Health::new(BTreeMap::from([
+        ("database", Component::check(database)),
+        ("server", Component::check(server)),
+    ]))
+}
+
+#[cfg(test)]
+mod test_health {
+    use super::*;
+
+    use crate::repository::MemoryUserRepository;
+
+    #[tokio::test]
+    async fn test_ready() {
+        let health = readiness(&MemoryUserRepository::default(), &Lifecycle::default()).await;
+        assert_eq!(health.status_code(), StatusCode::OK);
+        assert_eq!(
+            serde_json::to_value(&health).unwrap(),
+            serde_json::json!({
+                "status": "up",
+                "components": {
+                    "database": { "status": "up" },
+                    "server": { "status": "up" },
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_not_ready() {
+        let users = MemoryUserRepository::default();
+        users.close().await;
+        let lifecycle = Lifecycle::default();
+        lifecycle.set_not_ready();
+        let health = readiness(&users, &lifecycle).await;
+        assert_eq!(health.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(health.components["database"].status, Status::Down);
+        // no driver details in the public response
+        assert_eq!(health.components["database"].error, Some("unavailable"));
+        assert_eq!(health.components["server"].error, Some("shutting down"));
+    }
+}
@@ -0,0 +1,281 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use axum::extract::{DefaultBodyLimit, Request, State};
+use axum::http::header::{
+    CONTENT_SECURITY_POLICY, STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS,
+};
+use axum::http::{HeaderName, HeaderValue};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use thiserror::Error;
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tower_http::set_header::SetResponseHeaderLayer;
+
+use crate::config::{CorsSettings, HttpSettings};
+use crate::error::ApiError;
+
+#[derive(Debug, Error)]
+pub enum LayerError {
+    #[error("invalid {kind} `{value}`")]
+    Invalid { kind: &'static str, value: String },
+    #[error("http.cors.allow_credentials cannot be combined with origin `*`")]
+    CredentialsWithAnyOrigin,
+}
+
+/// The tower layers configured in [`HttpSettings`], checked up front so that
+/// building the router cannot fail.
+#[derive(Debug, Clone)]
+pub struct HttpLayers {
+    body_limit: usize,
+    timeout: Option<Duration>,
+    concurrency_limit: usize,
+    cors: Option<CorsLayer>,
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl HttpLayers {
+    pub fn new(settings: &HttpSettings) -> Result<Self, LayerError> {
+        let security = &settings.security_headers;
+        let mut headers = Vec::new();
+        for (name, value) in [
+            (
+                STRICT_TRANSPORT_SECURITY,
+                &security.strict_transport_security,
+            ),
+            (CONTENT_SECURITY_POLICY, &security.content_security_policy),
+            (X_CONTENT_TYPE_OPTIONS, &security.content_type_options),
+        ] {
+            if value.is_empty() {
+                continue;
+            }
+            let value = HeaderValue::from_str(value).map_err(|_| LayerError::Invalid {
+                kind: "security header",
+                value: value.clone(),
+            })?;
+            headers.push((name, value));
+        }
+        Ok(Self {
+            body_limit: settings.body_limit_bytes,
+            timeout: settings.request_timeout(),
+            concurrency_limit: settings.concurrency_limit,
+            cors: cors(&settings.cors)?,
+            headers,
+        })
+    }
+
+    /// Body size, concurrency and time limits; inside the metrics so that
+    /// requests failing them are counted.
+    pub(crate) fn limit(&self, router: Router) -> Router {
+        let mut router = router.layer(DefaultBodyLimit::max(self.body_limit));
+        if self.concurrency_limit > 0 {
+            router = router.layer(GlobalConcurrencyLimitLayer::new(self.concurrency_limit));
+        }
+        // outside the concurrency limit, so waiting for a slot counts too
+        if let Some(timeout) = self.timeout {
+            router = router.layer(middleware::from_fn_with_state(timeout, time_out));
+        }
+        router
+    }
+
+    /// Security headers and CORS; outside the other middleware so that every
+    /// response carries them.
+    pub(crate) fn headers(&self, mut router: Router) -> Router {
+        for (name, value) in &self.headers {
+            router = router.layer(SetResponseHeaderLayer::if_not_present(
+                name.clone(),
+                value.clone(),
+            ));
+        }
+        match &self.cors {
+            Some(cors) => router.layer(cors.clone()),
+            None => router,
+        }
+    }
+}
+
+fn parse<T: FromStr>(kind: &'static str, values: &[String]) -> Result<Vec<T>, LayerError> {
+    values
+        .iter()
+        .map(|value| {
+            value.parse().map_err(|_| LayerError::Invalid {
+                kind,
+                value: value.clone(),
+            })
+        })
+        .collect()
+}
+
+fn cors(settings: &CorsSettings) -> Result<Option<CorsLayer>, LayerError> {
+    if settings.allowed_origins.is_empty() {
+        return Ok(None);
+    }
+    let origins = if settings.allowed_origins.iter().any(|origin| origin == "*") {
+        if settings.allow_credentials {
+            return Err(LayerError::CredentialsWithAnyOrigin);
+        }
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(parse::<HeaderValue>(
+            "CORS origin",
+            &settings.allowed_origins,
+        )?)
+    };
+    Ok(Some(
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(parse("CORS method", &settings.allowed_methods)?)
+            .allow_headers(parse::<HeaderName>(
+                "CORS header",
+                &settings.allowed_headers,
+            )?)
+            .expose_headers(parse::<HeaderName>(
+                "CORS header",
+                &settings.exposed_headers,
+            )?)
+            .allow_credentials(settings.allow_credentials)
+            .max_age(Duration::from_secs(settings.max_age_secs)),
+    ))
+}
+
+/// Fails requests that take longer than `timeout` with 504.
+async fn time_out(State(timeout): State<Duration>, request: Request, next: Next) -> Response {
+    tokio::time::timeout(timeout, next.run(request))
+        .await
+        .unwrap_or_else(|_| ApiError::Timeout.into_response())
+}
+
+#[cfg(test)]
+mod test_layers {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::header::{ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN};
+    use axum::http::{Method, StatusCode};
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    fn app(settings: &HttpSettings) -> Router {
+        let layers = HttpLayers::new(settings).unwrap();
+        let router = Router::new().route("/", get(|| async { "ok" })).route(
+            "/framed",
+            get(|| async { ([(CONTENT_SECURITY_POLICY, "frame-ancestors 'self'")], "ok") }),
+        );
+        layers.headers(layers.limit(router))
+    }
+
+    async fn send(app: Router, method: Method, uri: &str, origin: Option<&str>) -> Response {
+        let mut request = axum::http::Request::builder().method(method).uri(uri);
+        if let Some(origin) = origin {
+            request = request
+                .header(ORIGIN, origin)
+                .header(ACCESS_CONTROL_REQUEST_METHOD, "GET");
+        }
+        app.oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_security_headers() {
+        let router = app(&HttpSettings::default());
+        let response = send(router.clone(), Method::GET, "/", None).await;
+        let headers = response.headers();
+        assert_eq!(headers[X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert!(headers.contains_key(STRICT_TRANSPORT_SECURITY));
+        assert_eq!(
+            headers[CONTENT_SECURITY_POLICY],
+            "default-src 'none'; frame-ancestors 'none'"
+        );
+        // handlers can set their own policy
+        let response = send(router, Method::GET, "/framed", None).await;
+        assert_eq!(
+            response.headers()[CONTENT_SECURITY_POLICY],
+            "frame-ancestors 'self'"
+        );
+
+        let mut settings = HttpSettings::default();
+        settings.security_headers.strict_transport_security = String::new();
+        let response = send(app(&settings), Method::GET, "/", None).await;
+        assert!(!response.headers().contains_key(STRICT_TRANSPORT_SECURITY));
+    }
+
+    #[tokio::test]
+    async fn test_cors() {
+        let response = send(
+            app(&HttpSettings::default()),
+            Method::OPTIONS,
+            "/",
+            Some("https://app.example.com"),
+        )
+        .await;
+        assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        let mut settings = HttpSettings::default();
+        settings.cors.allowed_origins = vec!["https://app.example.com".to_string()];
+        let app = app(&settings);
+        let response = send(
+            app.clone(),
+            Method::OPTIONS,
+            "/",
+            Some("https://app.example.com"),
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+        let response = send(app, Method::OPTIONS, "/", Some("https://evil.example.com")).await;
+        assert!(!response.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let slow = |timeout: Duration| {
+            Router::new()
+                .route(
+                    "/",
+                    get(|| async {
+                        tokio::time::sleep(Duration::from_millis(200)).await;
+                        "slow"
+                    }),
+                )
+                .layer(middleware::from_fn_with_state(timeout, time_out))
+        };
+        let response = send(slow(Duration::from_millis(10)), Method::GET, "/", None).await;
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        let response = send(slow(Duration::from_secs(10)), Method::GET, "/", None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let settings = HttpSettings {
+            request_timeout_secs: 0,
+            ..HttpSettings::default()
+        };
+        assert_eq!(settings.request_timeout(), None);
+    }
+
+    #[test]
+    fn test_invalid_settings() {
+        let mut settings = HttpSettings::default();
+        settings.cors.allowed_origins = vec!["*".to_string()];
+        settings.cors.allow_credentials = true;
+        assert!(matches!(
+            HttpLayers::new(&settings),
+            Err(LayerError::CredentialsWithAnyOrigin)
+        ));
+        settings.cors.allow_credentials = false;
+        settings.cors.allowed_headers = vec!["not a header".to_string()];
+        assert!(matches!(
+            HttpLayers::new(&settings),
+            Err(LayerError::Invalid {
+                kind: "CORS header",
+                ..
+            })
+        ));
+        let mut settings = HttpSettings::default();
+        settings.security_headers.content_security_policy = "line\nbreak".to_string();
+        assert!(HttpLayers::new(&settings).is_err());
+    }
+}
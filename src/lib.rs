@@ -0,0 +1,171 @@
+//! User registration and login API.
+//!
+//! The validated domain types ([`UserName`], [`Email`], [`Password`]) live at
+//! the crate root, [`build_router`] assembles the HTTP API around an [`AppState`].
+
+use std::sync::Arc;
+
+use axum::extract::FromRef;
+use axum::handler::Handler;
+use axum::response::Html;
+use axum::routing::{get, post};
+use axum::{middleware, Router};
+use tower::Layer;
+
+use access::{Guard, Permission, Rule};
+use config::{ClientRoute, DeprecatedRoute};
+use layers::HttpLayers;
+use metrics::Metrics;
+use openapi::DocsScript;
+use rate_limit::RateLimiter;
+use repository::UserRepository;
+use shutdown::Lifecycle;
+use token::TokenKeys;
+use validation::ValidationPolicy;
+use verification::EmailVerifier;
+
+pub mod access;
+pub mod auth;
+pub mod client_cert;
+pub mod config;
+mod domain;
+pub mod error;
+pub mod health;
+pub mod layers;
+pub mod mail;
+pub mod metrics;
+pub mod openapi;
+pub mod rate_limit;
+pub mod repository;
+pub mod shutdown;
+pub mod telemetry;
+pub mod tls;
+pub mod token;
+pub mod users;
+pub mod validation;
+pub mod verification;
+pub mod versioning;
+
+pub use domain::{
+    CharClass, Email, EmailError, EmailPolicy, Password, PasswordError, PasswordPolicy, UserName,
+    UserNameError, UserNamePattern, UserNamePolicy,
+};
+
+/// Everything the handlers share.
+#[derive(Clone)]
+pub struct AppState {
+    pub users: Arc<dyn UserRepository>,
+    pub policy: Arc<ValidationPolicy>,
+    pub tokens: Arc<TokenKeys>,
+    pub verifier: Arc<EmailVerifier>,
+    pub lifecycle: Lifecycle,
+    pub metrics: Arc<Metrics>,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub client_routes: Arc<Vec<ClientRoute>>,
+    pub deprecated_routes: Arc<Vec<DeprecatedRoute>>,
+    pub docs: DocsScript,
+}
+
+impl FromRef<AppState> for Arc<ValidationPolicy> {
+    fn from_ref(state: &AppState) -> Self {
+        state.policy.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<TokenKeys> {
+    fn from_ref(state: &AppState) -> Self {
+        state.tokens.clone()
+    }
+}
+
+/// The user resource and authentication, served under `/v1`.
+///
+/// Guarded handlers declare what the caller needs, see [`access::authorize`].
+fn v1(state: &AppState) -> Router<AppState> {
+    let require = |rule| {
+        middleware::from_fn_with_state(
+            Guard {
+                state: state.clone(),
+                rule,
+            },
+            access::authorize,
+        )
+    };
+    Router::new()
+        .route("/user/verify", get(verification::verify_email))
+        // `POST /users` goes to `create_user`
+        .route(
+            "/users",
+            get(users::list_users.layer(require(Rule::Permission(Permission::ListUsers))))
+                .post(users::create_user),
+        )
+        .route("/users/me", get(users::me))
+        .route(
+            "/users/:id",
+            get(users::get_user.layer(require(Rule::OwnerOr(Permission::ReadUsers))))
+                .patch(
+                    users::update_user
+                        .layer(require(Rule::OwnerOrLowerRole(Permission::UpdateUsers))),
+                )
+                .delete(
+                    users::delete_user
+                        .layer(require(Rule::OwnerOrLowerRole(Permission::DeleteUsers))),
+                ),
+        )
+        .route(
+            "/users/:id/access",
+            get(access::get_access.layer(require(Rule::OwnerOr(Permission::ReadUsers)))).put(
+                access::update_access.layer(require(Rule::Permission(Permission::ManageRoles))),
+            ),
+        )
+        .route("/auth/login", post(auth::login))
+        .route("/auth/refresh", post(auth::refresh))
+}
+
+/// All routes of the API with their middleware.
+///
+/// Serve it with `into_make_service_with_connect_info::<SocketAddr>()` so that
+/// rate limits can tell clients apart by address.
+pub fn build_router(state: AppState, layers: &HttpLayers) -> Router {
+    let metrics = state.metrics.clone();
+    let lifecycle = state.lifecycle.clone();
+    // served until the sunset in `api.deprecated_routes`
+    #[allow(deprecated)]
+    let create_user_legacy = post(users::create_user_legacy);
+    let router = Router::new()
+        // `GET /` goes to `root`
+        .route("/hello", get(|| async move { Html("<p> Hello World</p>") }))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        .route("/version", get(health::version))
+        .route("/metrics", get(metrics::metrics))
+        // deprecated alias of `POST /v1/users`, see `versioning::ALIASES`
+        .route("/user/create", create_user_legacy)
+        .nest("/v1", v1(&state))
+        .merge(openapi::routes(&state.policy, &state.docs))
+        .fallback(versioning::fallback)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::limit,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.client_routes.clone(),
+            client_cert::authorize,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.deprecated_routes.clone(),
+            versioning::deprecation,
+        ))
+        .with_state(state);
+    let router = layers
+        .limit(router)
+        .layer(middleware::from_fn_with_state(metrics, metrics::track))
+        .layer(middleware::from_fn_with_state(
+            lifecycle,
+            shutdown::close_when_not_ready,
+        ));
+    let router = layers
+        .headers(router)
+        .layer(middleware::from_fn(telemetry::trace_request));
+    Router::new().fallback_service(middleware::from_fn(versioning::negotiate).layer(router))
+}
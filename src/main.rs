@@ -1,176 +1,134 @@
-use axum::*;
-use email_address::EmailAddress;
-use http::StatusCode;
-use response::Html;
-use routing::{get, post};
-use serde::Deserialize;
-use thiserror::Error;
+use std::future::{Future, IntoFuture};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use clap::Parser;
+use pirate_api::config::{Cli, Settings};
+use pirate_api::error::StartupError;
+use pirate_api::layers::HttpLayers;
+use pirate_api::metrics::Metrics;
+use pirate_api::openapi::DocsScript;
+use pirate_api::rate_limit::RateLimiter;
+use pirate_api::shutdown::{self, Lifecycle};
+use pirate_api::tls::{self, CertResolver};
+use pirate_api::token::{TokenKeys, TokenSettings};
+use pirate_api::verification::EmailVerifier;
+use pirate_api::{access, build_router, mail, repository, telemetry, AppState};
+use tokio::net::TcpListener;
 
 #[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt::init();
-    // build our application with a route
-    let app = Router::new()
-        // `GET /` goes to `root`
-        .route("/hello", get(|| async move { Html("<p> Hello World</p>") }))
-        .route("/user/create", post(create_user));
-    // `POST /users` goes to `create_user`
-
-    // run our app with hyper, listening globally on port 3000
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
-}
-
-async fn create_user(Json(_): Json<CreateUser>) -> StatusCode {
-    StatusCode::OK
-}
-#[allow(dead_code)]
-#[derive(Deserialize)]
-struct CreateUser {
-    username: UserName,
-    email: Email,
-}
-#[derive(Deserialize, Debug)]
-#[serde(try_from = "String")]
-struct Email(String);
-
-#[derive(Debug, Error)]
-#[error("Error with validating Email")]
-pub struct EmailError;
-
-impl Email {
-    pub fn try_new(email: String) -> Result<Self, EmailError> {
-        if EmailAddress::is_valid(&email) {
-            Ok(Self(email))
-        } else {
-            Err(EmailError)
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(&cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
         }
     }
-    #[allow(dead_code)]
-    pub fn get(&self) -> &str {
-        &self.0
-    }
-}
-
-impl TryFrom<String> for Email {
-    type Error = EmailError;
-
-    fn try_from(value: String) -> Result<Self, Self::Error> {
-        Email::try_new(value)
-    }
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(try_from = "String")]
-struct UserName(String);
-
-#[derive(Debug, Error, PartialEq, Eq)]
-pub enum UserNameError {
-    #[error("Username is too short; It needs a minimum length of 12 Characters")]
-    TooShort,
-    #[error("Username is too long; The maximum Length is 32")]
-    TooLong,
-    #[error("Invalid Character {0} in Username")]
-    InvalidCharacter(String),
-}
-
-impl UserName {
-    pub fn try_new(username: String) -> Result<Self, UserNameError> {
-        let invalid_chars = "!§$%&/()=?";
-        if username.len() < 12 {
-            Err(UserNameError::TooShort)
-        } else if username.len() >= 32 {
-            Err(UserNameError::TooLong)
-        } else {
-            for char in username.chars() {
-                if invalid_chars.contains(char) {
-                    return Err(UserNameError::InvalidCharacter(char.to_string()));
-                }
+async fn run(cli: &Cli) -> Result<(), StartupError> {
+    let mut settings = Settings::load(cli)?;
+    settings
+        .validation
+        .email
+        .load_disposable_domains()
+        .map_err(StartupError::DisposableDomains)?;
+    let telemetry = telemetry::init(&settings.log, &settings.otlp)?;
+    let users = repository::connect(&settings.database.url).await?;
+    access::bootstrap_admins(&*users, &settings.auth.admin_ids).await?;
+    let token_settings =
+        TokenSettings::from_config(&settings.auth).map_err(StartupError::KeyFile)?;
+    let tokens = TokenKeys::new(&token_settings)?;
+    let verifier = EmailVerifier {
+        mailer: mail::from_config(&settings.mail)?,
+        url: settings.mail.verification_url.clone(),
+        ttl: std::time::Duration::from_secs(settings.mail.verification_ttl_secs),
+    };
+    let lifecycle = Lifecycle::default();
+    let rate_limiter = Arc::new(RateLimiter::new(settings.rate_limit));
+    let cleanup_users = users.clone();
+    lifecycle.spawn(|cancel| shutdown::clean_refresh_tokens(cleanup_users, cancel));
+    lifecycle.spawn(|cancel| rate_limiter.clone().prune_periodically(cancel));
+    let state = AppState {
+        users: users.clone(),
+        policy: Arc::new(settings.validation),
+        tokens: Arc::new(tokens),
+        verifier: Arc::new(verifier),
+        lifecycle: lifecycle.clone(),
+        metrics: Arc::new(Metrics::default()),
+        rate_limiter,
+        client_routes: Arc::new(settings.server.tls.client_routes.clone()),
+        deprecated_routes: Arc::new(settings.api.deprecated_routes.clone()),
+        docs: DocsScript::from_settings(&settings.api).map_err(StartupError::DocsScript)?,
+    };
+    let app = build_router(state, &HttpLayers::new(&settings.http)?);
+
+    // run our app with hyper, listening on the configured address
+    let addr = settings.server.socket_addr();
+    let listener = bind(addr).await?;
+    let tls = match CertResolver::from_settings(&settings.server.tls)? {
+        Some(resolver) => {
+            let resolver = Arc::new(resolver);
+            let interval = settings.server.tls.reload_interval();
+            let watcher = resolver.clone();
+            lifecycle.spawn(|cancel| watcher.watch(interval, cancel));
+            if let Some(port) = settings.server.tls.redirect_port {
+                let redirect_addr = SocketAddr::new(settings.server.bind_address, port);
+                let redirect = bind(redirect_addr).await?;
+                tracing::info!("redirecting http://{redirect_addr} to HTTPS");
+                let https_port = settings.server.port;
+                lifecycle.spawn(|cancel| tls::serve_redirect(redirect, https_port, cancel));
             }
-            Ok(Self(username))
+            Some(Arc::new(resolver.server_config(&settings.server.tls)?))
+        }
+        None => None,
+    };
+    let scheme = if tls.is_some() { "https" } else { "http" };
+    tracing::info!("listening on {scheme}://{addr}");
+    let draining = tokio_util::sync::CancellationToken::new();
+    let signal = {
+        let (lifecycle, draining) = (lifecycle.clone(), draining.clone());
+        let delay = settings.server.shutdown_delay();
+        async move {
+            shutdown::signal().await;
+            tracing::info!("shutdown signal received, reporting not ready");
+            lifecycle.set_not_ready();
+            tokio::time::sleep(delay).await;
+            tracing::info!("draining connections");
+            draining.cancel();
+        }
+    };
+    let server: Pin<Box<dyn Future<Output = std::io::Result<()>> + Send>> = match tls {
+        Some(config) => Box::pin(tls::serve(listener, config, app, signal)),
+        None => Box::pin(
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(signal)
+            .into_future(),
+        ),
+    };
+    let timeout = settings.server.shutdown_timeout();
+    tokio::select! {
+        result = server => result.map_err(StartupError::Serve)?,
+        () = async { draining.cancelled().await; tokio::time::sleep(timeout).await } => {
+            tracing::warn!("requests still running after {}s, dropping them", timeout.as_secs());
         }
     }
-    #[allow(dead_code)]
-    pub fn get(&self) -> &str {
-        &self.0
-    }
-}
-
-impl TryFrom<String> for UserName {
-    type Error = UserNameError;
-
-    fn try_from(value: String) -> Result<Self, Self::Error> {
-        UserName::try_new(value)
-    }
-}
-
-#[cfg(test)]
-mod test_email {
-    use super::*;
-
-    #[test]
-    fn test_good() {
-        assert!(Email::try_new("example@s.example".to_string()).is_ok());
-        assert!(Email::try_new("admin@mailserver1".to_string()).is_ok());
-        assert!(Email::try_new("example-indeed@strange-example.com".to_string()).is_ok());
-    }
-
-    #[test]
-    fn test_bad() {
-        assert!(Email::try_new("this\\ still\"not\\allowed@example.com".to_string()).is_err());
-        assert!(Email::try_new("a\"b(c)d,e:f;g<h>i[j\\k]l@example.com".to_string()).is_err());
-        assert!(Email::try_new("A@b@c@example.com".to_string()).is_err());
-        assert!(Email::try_new("Abc.example.com".to_string()).is_err());
-        assert!(Email::try_new(
-            "1234567890123456789012345678901234567890123456789012345678901234+x@example.co"
-                .to_string()
-        )
-        .is_err());
-    }
+    lifecycle.stop_tasks().await;
+    users.close().await;
+    tracing::info!("shutdown complete");
+    telemetry.shutdown();
+    Ok(())
 }
 
-#[cfg(test)]
-mod test_username {
-    use super::*;
-
-    #[test]
-    fn test_good() {
-        assert!(UserName::try_new("HelloWorldIAmTim".to_string()).is_ok());
-        assert!(UserName::try_new("HelloWorld123141IAmTim".to_string()).is_ok());
-        assert!(UserName::try_new("HelloWorld.....IAmTim".to_string()).is_ok());
-    }
-
-    #[test]
-    fn test_bad() {
-        assert!(UserName::try_new("test".to_string()).is_err());
-        assert!(UserName::try_new("?testhallowkfahfla".to_string()).is_err());
-        assert!(
-            UserName::try_new("halloweltichbindertimundichhasselangeusernames".to_string())
-                .is_err()
-        );
-        assert!(UserName::try_new("test!%$/".to_string()).is_err());
-    }
-    #[test]
-    fn test_correct_errors() {
-        assert_eq!(
-            UserName::try_new("test".to_string()).unwrap_err(),
-            UserNameError::TooShort
-        );
-
-        assert_eq!(
-            UserName::try_new("?testhallowkfahfla".to_string()).unwrap_err(),
-            UserNameError::InvalidCharacter('?'.to_string())
-        );
-
-        assert_eq!(
-            UserName::try_new("halloweltichbindertimundichhasselangeusernames".to_string())
-                .unwrap_err(),
-            UserNameError::TooLong
-        );
-
-        assert_eq!(
-            UserName::try_new("test!%$/".to_string()).unwrap_err(),
-            UserNameError::TooShort
-        );
-    }
+async fn bind(addr: SocketAddr) -> Result<TcpListener, StartupError> {
+    TcpListener::bind(addr)
+        .await
+        .map_err(|source| StartupError::Bind { addr, source })
 }
@@ -0,0 +1,205 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::validation::ValidationErrors;
+use crate::AppState;
+
+/// Route label of requests that did not match any route.
+pub(crate) const UNMATCHED: &str = "unmatched";
+
+/// Prometheus metrics of the server, rendered by `GET /metrics`.
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    duration: HistogramVec,
+    in_flight: IntGaugeVec,
+    pub users_created: IntCounter,
+    validation_failures: IntCounterVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let registry = Registry::new();
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Handled HTTP requests"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time until the response head was ready",
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let in_flight = IntGaugeVec::new(
+            Opts::new("http_requests_in_flight", "HTTP requests being handled"),
+            &["method", "route"],
+        )
+        .unwrap();
+        let users_created =
+            IntCounter::new("users_created_total", "Successfully registered users").unwrap();
+        let validation_failures = IntCounterVec::new(
+            Opts::new(
+                "validation_failures_total",
+                "Rejected request fields by field and error code",
+            ),
+            &["field", "code"],
+        )
+        .unwrap();
+        for collector in [
+            Box::new(requests.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(duration.clone()),
+            Box::new(in_flight.clone()),
+            Box::new(users_created.clone()),
+            Box::new(validation_failures.clone()),
+        ] {
+            // the names above are distinct, so registering cannot fail
+            registry.register(collector).unwrap();
+        }
+        Self {
+            registry,
+            requests,
+            duration,
+            in_flight,
+            users_created,
+            validation_failures,
+        }
+    }
+}
+
+impl Metrics {
+    /// All metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        // encoding into a Vec only fails for malformed metric families
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
+
+/// Decrements the in-flight gauge also when the request future is dropped.
+struct InFlight(prometheus::IntGauge);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Records count, latency and in-flight requests per route, and the field
+/// errors of rejected request bodies.
+pub async fn track(State(metrics): State<Arc<Metrics>>, request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(UNMATCHED, MatchedPath::as_str)
+        .to_string();
+    let gauge = metrics.in_flight.with_label_values(&[&method, &route]);
+    gauge.inc();
+    let _in_flight = InFlight(gauge);
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+    let status = response.status().as_u16().to_string();
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+    metrics.requests.with_label_values(&labels).inc();
+    metrics
+        .duration
+        .with_label_values(&labels)
+        .observe(start.elapsed().as_secs_f64());
+
+    if let Some(errors) = response.extensions().get::<ValidationErrors>() {
+        for error in &errors.0 {
+            metrics
+                .validation_failures
+                .with_label_values(&[&error.field, error.code])
+                .inc();
+        }
+    }
+    response
+}
+
+/// Prometheus scrape endpoint.
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "health",
+    responses((status = OK, description = "Prometheus text format", body = String, content_type = "text/plain"))
+)]
+pub async fn metrics(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        state.metrics.render(),
+    )
+}
+
+#[cfg(test)]
+mod test_metrics {
+    use super::*;
+    use crate::error::ApiError;
+    use crate::validation::FieldError;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use tower::ServiceExt;
+
+    fn app(metrics: Arc<Metrics>) -> Router {
+        Router::new()
+            .route("/users/:id", get(|| async {}))
+            .route(
+                "/invalid",
+                get(|| async {
+                    let mut errors = ValidationErrors::default();
+                    errors.push(FieldError::new("username", "too_short", "too short"));
+                    errors.push(FieldError::new("username", "reserved", "reserved"));
+                    ApiError::Validation(errors)
+                }),
+            )
+            .layer(middleware::from_fn_with_state(metrics, track))
+    }
+
+    async fn get_uri(app: &Router, uri: &str) {
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        app.clone().oneshot(request).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_track() {
+        let metrics = Arc::new(Metrics::default());
+        let app = app(metrics.clone());
+        get_uri(&app, "/users/1").await;
+        get_uri(&app, "/users/2").await;
+        get_uri(&app, "/missing").await;
+        get_uri(&app, "/invalid").await;
+        metrics.users_created.inc();
+
+        let text = metrics.render();
+        assert!(
+            text.contains(r#"http_requests_total{method="GET",route="/users/:id",status="200"} 2"#)
+        );
+        assert!(
+            text.contains(r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#)
+        );
+        assert!(text.contains(
+            r#"http_request_duration_seconds_count{method="GET",route="/users/:id",status="200"} 2"#
+        ));
+        assert!(text.contains(r#"http_requests_in_flight{method="GET",route="/users/:id"} 0"#));
+        assert!(text.contains(r#"validation_failures_total{code="too_short",field="username"} 1"#));
+        assert!(text.contains(r#"validation_failures_total{code="reserved",field="username"} 1"#));
+        assert!(text.contains("users_created_total 1"));
+    }
+}
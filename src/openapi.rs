@@ -0,0 +1,406 @@
+use std::borrow::Cow;
+
+use axum::body::Bytes;
+use axum::http::header::{CONTENT_SECURITY_POLICY, CONTENT_TYPE};
+use axum::response::Html;
+use axum::routing::get;
+use axum::{Json, Router};
+use utoipa::openapi::schema::{KnownFormat, ObjectBuilder, SchemaFormat, Type};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::{OpenApi as OpenApiDoc, RefOr, Schema};
+use utoipa::{Modify, OpenApi, PartialSchema, ToSchema};
+
+use crate::config::ApiSettings;
+use crate::validation::ValidationPolicy;
+use crate::{
+    access, auth, health, metrics, users, verification, CharClass, Email, EmailPolicy, Password,
+    PasswordPolicy, UserName, UserNamePolicy,
+};
+
+/// Scalar API reference pointed at `/openapi.json`; `{script}` is replaced
+/// by where the script is loaded from.
+const DOCS_HTML: &str = r#"<!doctype html>
+<html>
+  <head>
+    <title>Pirate API</title>
+    <meta charset="utf-8" />
+    <meta name="viewport" content="width=device-width, initial-scale=1" />
+  </head>
+  <body>
+    <script id="api-reference" data-url="/openapi.json" data-configuration='{"withDefaultFonts": false}'></script>
+    <script src="{script}"></script>
+  </body>
+</html>
+"#;
+
+const CDN_SCRIPT: &str = "https://cdn.jsdelivr.net/npm/@scalar/api-reference";
+
+/// Replaces the strict default policy, the reference is loaded from its CDN.
+const CDN_CSP: &str = "default-src 'self'; script-src https://cdn.jsdelivr.net; \
+    style-src 'self' 'unsafe-inline' https:; font-src 'self' https: data:; \
+    img-src 'self' https: data:; connect-src 'self'";
+
+/// Path of the local copy of the script.
+const LOCAL_SCRIPT: &str = "/docs/scalar.js";
+
+/// Nothing but this server, for a local copy of the script.
+const LOCAL_CSP: &str = "default-src 'self'; style-src 'self' 'unsafe-inline'; \
+    img-src 'self' data:; font-src 'self' data:";
+
+/// Where `/docs` loads the Scalar API reference from, see `api.docs_script_file`.
+#[derive(Debug, Clone, Default)]
+pub enum DocsScript {
+    /// The jsDelivr CDN; needs internet access in the browser.
+    #[default]
+    Cdn,
+    /// A local copy of the script, served at `/docs/scalar.js`.
+    Local(Bytes),
+}
+
+impl DocsScript {
+    pub fn from_settings(settings: &ApiSettings) -> std::io::Result<Self> {
+        Ok(match &settings.docs_script_file {
+            Some(path) => Self::Local(std::fs::read(path)?.into()),
+            None => Self::Cdn,
+        })
+    }
+
+    fn page(&self) -> (&'static str, String) {
+        match self {
+            DocsScript::Cdn => (CDN_CSP, DOCS_HTML.replace("{script}", CDN_SCRIPT)),
+            DocsScript::Local(_) => (LOCAL_CSP, DOCS_HTML.replace("{script}", LOCAL_SCRIPT)),
+        }
+    }
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Pirate API"),
+    paths(
+        users::list_users,
+        users::create_user,
+        users::create_user_legacy,
+        users::me,
+        users::get_user,
+        users::update_user,
+        users::delete_user,
+        access::get_access,
+        access::update_access,
+        verification::verify_email,
+        auth::login,
+        auth::refresh,
+        health::healthz,
+        health::readyz,
+        health::version,
+        metrics::metrics,
+    ),
+    components(schemas(crate::error::ErrorBody)),
+    modifiers(&BearerAuth),
+    tags(
+        (name = "users", description = "Registration and user management"),
+        (name = "auth", description = "Access and refresh tokens"),
+        (name = "health", description = "Liveness, readiness, metrics and build information"),
+    )
+)]
+struct ApiDoc;
+
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut OpenApiDoc) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "bearer",
+                SecurityScheme::Http(
+                    HttpBuilder::new()
+                        .scheme(HttpAuthScheme::Bearer)
+                        .bearer_format("JWT")
+                        .build(),
+                ),
+            );
+        }
+    }
+}
+
+/// The specification for `policy`, so that documented constraints such as
+/// username lengths always match what the server enforces.
+pub fn spec(policy: &ValidationPolicy) -> OpenApiDoc {
+    let mut openapi = ApiDoc::openapi();
+    openapi.info.version = env!("CARGO_PKG_VERSION").to_string();
+    if let Some(components) = openapi.components.as_mut() {
+        for (name, schema) in [
+            (UserName::name(), username_schema(&policy.username)),
+            (Email::name(), email_schema(&policy.email)),
+            (Password::name(), password_schema(&policy.password)),
+        ] {
+            components.schemas.insert(name.into_owned(), schema.into());
+        }
+    }
+    openapi
+}
+
+/// `GET /openapi.json` and the interactive documentation at `GET /docs`.
+pub fn routes<S: Clone + Send + Sync + 'static>(
+    policy: &ValidationPolicy,
+    docs: &DocsScript,
+) -> Router<S> {
+    let spec = spec(policy);
+    let (csp, html) = docs.page();
+    let router = Router::new()
+        .route("/openapi.json", get(move || async move { Json(spec) }))
+        .route(
+            "/docs",
+            get(move || async move { ([(CONTENT_SECURITY_POLICY, csp)], Html(html)) }),
+        );
+    match docs {
+        DocsScript::Local(script) => {
+            let script = script.clone();
+            router.route(
+                LOCAL_SCRIPT,
+                get(move || async move { ([(CONTENT_TYPE, "text/javascript")], script) }),
+            )
+        }
+        DocsScript::Cdn => router,
+    }
+}
+
+fn username_schema(policy: &UserNamePolicy) -> Schema {
+    let mut description = format!(
+        "Normalized to NFKC before validation. Lengths are counted in grapheme clusters. \
+         Names that look like an existing name are rejected as duplicates. \
+         Forbidden characters: `{}`.",
+        policy.forbidden_characters
+    );
+    if !policy.allowed_classes.is_empty() {
+        let classes: Vec<_> = policy
+            .allowed_classes
+            .iter()
+            .map(|class| match class {
+                CharClass::Letter => "letters",
+                CharClass::Digit => "digits",
+                CharClass::Whitespace => "whitespace",
+                CharClass::Punctuation => "ASCII punctuation",
+            })
+            .collect();
+        description.push_str(&format!(" Allowed characters: {}.", classes.join(", ")));
+    }
+    // the lists themselves are the operator's business, not the public spec's
+    if !policy.reserved_names.is_empty() {
+        description.push_str(if policy.case_sensitive {
+            " Some names are reserved."
+        } else {
+            " Some names are reserved, regardless of case."
+        });
+    }
+    let pattern = match &policy.pattern {
+        Some(pattern) => String::from(pattern.clone()),
+        None if policy.forbidden_characters.is_empty() => String::new(),
+        None => format!("^[^{}]*$", class_escape(&policy.forbidden_characters)),
+    };
+    ObjectBuilder::new()
+        .schema_type(Type::String)
+        .min_length(Some(policy.min_length))
+        .max_length(Some(policy.max_length))
+        .pattern((!pattern.is_empty()).then_some(pattern))
+        .description(Some(description))
+        .examples(["HelloWorldIAmTim"])
+        .into()
+}
+
+/// Escapes `chars` for use inside a character class, in a way both the
+/// `regex` crate and ECMAScript (as used by JSON Schema) understand.
+fn class_escape(chars: &str) -> String {
+    let mut escaped = String::new();
+    for (i, c) in chars.char_indices() {
+        // repeated characters could form `regex` class operators such as `&&`
+        if chars[..i].contains(c) {
+            continue;
+        }
+        if matches!(c, '\\' | ']' | '[' | '^' | '-') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn email_schema(policy: &EmailPolicy) -> Schema {
+    let mut description = "Addresses that only differ in case, or in dots and `+tag` \
+                           suffixes at providers that ignore them, count as the same address."
+        .to_string();
+    if !policy.allowed_domains.is_empty() {
+        description.push_str(" Only addresses at certain domains are accepted.");
+    }
+    if !policy.denied_domains.is_empty() {
+        description.push_str(" Addresses at some domains are rejected.");
+    }
+    if !policy.disposable_domains.is_empty() {
+        description.push_str(" Disposable email providers are rejected.");
+    }
+    ObjectBuilder::new()
+        .schema_type(Type::String)
+        .format(Some(SchemaFormat::KnownFormat(KnownFormat::Email)))
+        .description(Some(description))
+        .examples(["tim@example.com"])
+        .into()
+}
+
+fn password_schema(policy: &PasswordPolicy) -> Schema {
+    let required: Vec<_> = [
+        (policy.require_lowercase, "a lowercase letter"),
+        (policy.require_uppercase, "an uppercase letter"),
+        (policy.require_digit, "a digit"),
+        (policy.require_special, "a special character"),
+    ]
+    .into_iter()
+    .filter_map(|(required, what)| required.then_some(what))
+    .collect();
+    let description = if required.is_empty() {
+        None
+    } else {
+        Some(format!("Needs at least {}.", required.join(", ")))
+    };
+    ObjectBuilder::new()
+        .schema_type(Type::String)
+        .format(Some(SchemaFormat::KnownFormat(KnownFormat::Password)))
+        .min_length(Some(policy.min_length))
+        .max_length(Some(policy.max_length))
+        .description(description)
+        .into()
+}
+
+// The validated request types are documented through `spec`; these impls only
+// let the derived schemas reference them by name.
+
+impl PartialSchema for UserName {
+    fn schema() -> RefOr<Schema> {
+        username_schema(&UserNamePolicy::default()).into()
+    }
+}
+
+impl ToSchema for UserName {
+    fn name() -> Cow<'static, str> {
+        Cow::Borrowed("UserName")
+    }
+}
+
+impl PartialSchema for Email {
+    fn schema() -> RefOr<Schema> {
+        email_schema(&EmailPolicy::default()).into()
+    }
+}
+
+impl ToSchema for Email {
+    fn name() -> Cow<'static, str> {
+        Cow::Borrowed("Email")
+    }
+}
+
+impl PartialSchema for Password {
+    fn schema() -> RefOr<Schema> {
+        password_schema(&PasswordPolicy::default()).into()
+    }
+}
+
+impl ToSchema for Password {
+    fn name() -> Cow<'static, str> {
+        Cow::Borrowed("Password")
+    }
+}
+
+#[cfg(test)]
+mod test_openapi {
+    use super::*;
+
+    fn spec_json(policy: &ValidationPolicy) -> serde_json::Value {
+        serde_json::to_value(spec(policy)).unwrap()
+    }
+
+    #[test]
+    fn test_paths() {
+        let spec = spec_json(&ValidationPolicy::default());
+        assert!(spec["openapi"].as_str().unwrap().starts_with("3.1"));
+        for path in [
+            "/user/create",
+            "/v1/users",
+            "/v1/users/{id}",
+            "/v1/users/me",
+            "/v1/users/{id}/access",
+            "/v1/user/verify",
+            "/v1/auth/login",
+        ] {
+            assert!(spec["paths"][path].is_object(), "{path} is missing");
+        }
+        assert_eq!(spec["paths"]["/user/create"]["post"]["deprecated"], true);
+        assert_eq!(
+            spec["components"]["schemas"]["CreateUser"]["properties"]["username"]["$ref"],
+            "#/components/schemas/UserName"
+        );
+        assert!(spec["components"]["securitySchemes"]["bearer"].is_object());
+    }
+
+    #[tokio::test]
+    async fn test_docs_script() {
+        use axum::body::{to_bytes, Body};
+        use tower::ServiceExt;
+
+        let get = |router: Router, uri: &str| {
+            let request = axum::http::Request::get(uri).body(Body::empty()).unwrap();
+            async move { router.oneshot(request).await.unwrap() }
+        };
+        let policy = ValidationPolicy::default();
+        let cdn: Router = routes(&policy, &DocsScript::Cdn);
+        let response = get(cdn.clone(), "/docs").await;
+        assert_eq!(response.headers()[CONTENT_SECURITY_POLICY], CDN_CSP);
+        assert_eq!(
+            get(cdn, LOCAL_SCRIPT).await.status(),
+            axum::http::StatusCode::NOT_FOUND
+        );
+
+        let local: Router = routes(&policy, &DocsScript::Local(Bytes::from("// scalar")));
+        let response = get(local.clone(), "/docs").await;
+        assert_eq!(response.headers()[CONTENT_SECURITY_POLICY], LOCAL_CSP);
+        let html = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let html = std::str::from_utf8(&html).unwrap();
+        assert!(html.contains(r#"<script src="/docs/scalar.js">"#));
+        assert!(!html.contains("cdn.jsdelivr.net"));
+        let script = get(local, LOCAL_SCRIPT).await;
+        assert_eq!(script.headers()[CONTENT_TYPE], "text/javascript");
+        assert_eq!(
+            to_bytes(script.into_body(), usize::MAX).await.unwrap(),
+            "// scalar"
+        );
+    }
+
+    #[test]
+    fn test_policy_constraints() {
+        let mut policy = ValidationPolicy::default();
+        policy.username.min_length = 5;
+        policy.username.max_length = 20;
+        policy.username.forbidden_characters = "!]".to_string();
+        policy.username.reserved_names = vec!["Blackbeard".to_string()];
+        policy.email.allowed_domains = vec!["pirates.example".to_string()];
+        policy.email.denied_domains = vec!["navy.example".to_string()];
+        policy.password.min_length = 30;
+        let spec = spec_json(&policy);
+        let schemas = &spec["components"]["schemas"];
+
+        let username = &schemas["UserName"];
+        assert_eq!(username["minLength"], 5);
+        assert_eq!(username["maxLength"], 20);
+        let pattern = regex::Regex::new(username["pattern"].as_str().unwrap()).unwrap();
+        assert!(pattern.is_match("HelloWorld"));
+        assert!(!pattern.is_match("Hello]World"));
+        assert!(!pattern.is_match("HelloWorld!"));
+        let description = username["description"].as_str().unwrap();
+        assert!(description.contains("reserved"));
+
+        assert_eq!(schemas["Password"]["minLength"], 30);
+        assert_eq!(schemas["Email"]["format"], "email");
+        // the policy lists stay private
+        let text = spec.to_string();
+        for name in ["Blackbeard", "pirates.example", "navy.example"] {
+            assert!(!text.contains(name), "{name} is published");
+        }
+    }
+}
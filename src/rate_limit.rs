@@ -0,0 +1,305 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::{ConnectInfo, MatchedPath, Request, State};
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use ipnet::IpNet;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{route_or_alias_matches, RateLimitKey, RateLimitRule, RateLimitSettings};
+use crate::error::ApiError;
+use crate::token::TokenKeys;
+use crate::AppState;
+
+const RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("ratelimit-limit");
+const RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("ratelimit-remaining");
+const RATELIMIT_RESET: HeaderName = HeaderName::from_static("ratelimit-reset");
+const RATELIMIT_POLICY: HeaderName = HeaderName::from_static("ratelimit-policy");
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Outcome of taking a token from a bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Decision {
+    allowed: bool,
+    limit: u32,
+    remaining: u32,
+    /// Seconds until the bucket is full again.
+    reset: u64,
+    /// Seconds until the next token is available, if none is left.
+    retry_after: u64,
+}
+
+/// Token buckets per rule and client, see [`RateLimitSettings`].
+pub struct RateLimiter {
+    settings: RateLimitSettings,
+    buckets: Mutex<HashMap<(usize, String), Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(settings: RateLimitSettings) -> Self {
+        Self {
+            settings,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn rule(&self, method: &str, route: &str) -> Option<(usize, &RateLimitRule)> {
+        if !self.settings.enabled {
+            return None;
+        }
+        self.settings.rules.iter().enumerate().find(|(_, rule)| {
+            route_or_alias_matches(rule.method.as_deref(), &rule.route, method, route)
+        })
+    }
+
+    fn take(&self, index: usize, client: String, now: Instant) -> Decision {
+        let rule = &self.settings.rules[index];
+        let capacity = f64::from(rule.requests.get());
+        let rate = refill_rate(rule);
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry((index, client)).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        Decision {
+            allowed,
+            limit: rule.requests.get(),
+            remaining: bucket.tokens.floor() as u32,
+            reset: ((capacity - bucket.tokens) / rate).ceil() as u64,
+            retry_after: if allowed {
+                0
+            } else {
+                ((1.0 - bucket.tokens) / rate).ceil() as u64
+            },
+        }
+    }
+
+    /// Forgets buckets that have refilled completely, they are equal to new ones.
+    fn prune(&self, now: Instant) {
+        let rules = &self.settings.rules;
+        self.buckets.lock().unwrap().retain(|(index, _), bucket| {
+            let rule = &rules[*index];
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens + elapsed * refill_rate(rule) < f64::from(rule.requests.get())
+        });
+    }
+
+    /// Periodically prunes idle buckets until `cancel` is triggered.
+    pub async fn prune_periodically(self: Arc<Self>, cancel: CancellationToken) {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            tokio::select! {
+                () = cancel.cancelled() => return,
+                _ = interval.tick() => self.prune(Instant::now()),
+            }
+        }
+    }
+
+    /// The client address: the peer, or for trusted proxies the last untrusted
+    /// address in `X-Forwarded-For`.
+    fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let trusted = |ip: &IpAddr| {
+            self.settings
+                .trusted_proxies
+                .iter()
+                .any(|net: &IpNet| net.contains(ip))
+        };
+        if !trusted(&peer) {
+            return peer;
+        }
+        let forwarded: Vec<IpAddr> = headers
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|ip| ip.trim().parse().ok())
+            .collect();
+        forwarded
+            .iter()
+            .rev()
+            .find(|ip| !trusted(ip))
+            .or(forwarded.first())
+            .copied()
+            .unwrap_or(peer)
+    }
+}
+
+/// Tokens per second.
+fn refill_rate(rule: &RateLimitRule) -> f64 {
+    f64::from(rule.requests.get()) / rule.period_secs.max(1) as f64
+}
+
+fn bearer_user(keys: &TokenKeys, headers: &HeaderMap) -> Option<i64> {
+    let token = headers
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")?;
+    keys.verify(token.trim()).ok()
+}
+
+/// Rejects requests over the limit of their route with 429 and reports the
+/// state of the bucket in `RateLimit-*` headers.
+pub async fn limit(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let limiter = &state.rate_limiter;
+    let Some(route) = request.extensions().get::<MatchedPath>() else {
+        return next.run(request).await;
+    };
+    let Some((index, rule)) = limiter.rule(request.method().as_str(), route.as_str()) else {
+        return next.run(request).await;
+    };
+    let user = match rule.key {
+        RateLimitKey::User => bearer_user(&state.tokens, request.headers()),
+        RateLimitKey::Ip => None,
+    };
+    let client = match user {
+        Some(id) => format!("user:{id}"),
+        None => {
+            let peer = request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip());
+            match peer {
+                Some(peer) => format!("ip:{}", limiter.client_ip(peer, request.headers())),
+                None => "ip:unknown".to_string(),
+            }
+        }
+    };
+
+    let decision = limiter.take(index, client, Instant::now());
+    let mut response = if decision.allowed {
+        next.run(request).await
+    } else {
+        ApiError::RateLimited {
+            retry_after: decision.retry_after,
+        }
+        .into_response()
+    };
+    let headers = response.headers_mut();
+    headers.insert(RATELIMIT_LIMIT, HeaderValue::from(decision.limit));
+    headers.insert(RATELIMIT_REMAINING, HeaderValue::from(decision.remaining));
+    headers.insert(RATELIMIT_RESET, HeaderValue::from(decision.reset));
+    if let Ok(policy) = HeaderValue::from_str(&format!("{};w={}", rule.requests, rule.period_secs))
+    {
+        headers.insert(RATELIMIT_POLICY, policy);
+    }
+    response
+}
+
+#[cfg(test)]
+mod test_rate_limit {
+    use super::*;
+
+    fn limiter(trusted_proxies: &[&str]) -> RateLimiter {
+        RateLimiter::new(RateLimitSettings {
+            enabled: true,
+            trusted_proxies: trusted_proxies.iter().map(|n| n.parse().unwrap()).collect(),
+            rules: vec![RateLimitRule {
+                method: Some("POST".to_string()),
+                route: "/users".to_string(),
+                requests: 2.try_into().unwrap(),
+                period_secs: 10,
+                key: RateLimitKey::Ip,
+            }],
+        })
+    }
+
+    #[test]
+    fn test_rule() {
+        let limiter = limiter(&[]);
+        assert!(limiter.rule("post", "/users").is_some());
+        assert!(limiter.rule("GET", "/users").is_none());
+        assert!(limiter.rule("POST", "/users/me").is_none());
+    }
+
+    #[test]
+    fn test_token_bucket() {
+        let limiter = limiter(&[]);
+        let start = Instant::now();
+        let take = |client: &str, secs: u64| {
+            limiter.take(0, client.to_string(), start + Duration::from_secs(secs))
+        };
+        assert_eq!(
+            take("a", 0),
+            Decision {
+                allowed: true,
+                limit: 2,
+                remaining: 1,
+                reset: 5,
+                retry_after: 0
+            }
+        );
+        assert!(take("a", 0).allowed);
+        let rejected = take("a", 1);
+        assert!(!rejected.allowed);
+        assert_eq!(rejected.remaining, 0);
+        assert_eq!(rejected.retry_after, 4);
+        // other clients have their own bucket
+        assert!(take("b", 1).allowed);
+        // one token every five seconds
+        assert!(take("a", 5).allowed);
+        assert!(!take("a", 6).allowed);
+    }
+
+    #[test]
+    fn test_prune() {
+        let limiter = limiter(&[]);
+        let start = Instant::now();
+        limiter.take(0, "a".to_string(), start);
+        limiter.prune(start + Duration::from_secs(1));
+        assert_eq!(limiter.buckets.lock().unwrap().len(), 1);
+        limiter.prune(start + Duration::from_secs(5));
+        assert!(limiter.buckets.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_client_ip() {
+        let limiter = limiter(&["10.0.0.0/8"]);
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(X_FORWARDED_FOR, value.parse().unwrap());
+            headers
+        };
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+        let forwarded = headers("203.0.113.7, 198.51.100.1, 10.0.0.2");
+
+        // only trusted proxies may name the client
+        assert_eq!(
+            limiter.client_ip(ip("192.0.2.1"), &forwarded),
+            ip("192.0.2.1")
+        );
+        assert_eq!(
+            limiter.client_ip(ip("10.0.0.1"), &forwarded),
+            ip("198.51.100.1")
+        );
+        assert_eq!(
+            limiter.client_ip(ip("10.0.0.1"), &headers("10.0.0.3")),
+            ip("10.0.0.3")
+        );
+        assert_eq!(
+            limiter.client_ip(ip("10.0.0.1"), &HeaderMap::new()),
+            ip("10.0.0.1")
+        );
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use super::UserRepository;
+use crate::access::{Access, Permission, Role};
+use crate::error::ApiError;
+use crate::token::unix_now;
+use crate::users::{UpdateUser, User};
+use crate::{Email, UserName};
+
+/// Users kept in memory, for tests and local experiments; nothing survives a restart.
+#[derive(Default)]
+pub struct MemoryUserRepository {
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    closed: bool,
+    last_id: i64,
+    users: BTreeMap<i64, Row>,
+    /// User id and expiry by token hash.
+    refresh_tokens: HashMap<String, (i64, i64)>,
+    /// User id by token hash.
+    verification_tokens: HashMap<String, i64>,
+}
+
+struct Row {
+    user: User,
+    username_skeleton: String,
+    email_canonical: String,
+    password_hash: Option<String>,
+    /// Granted on top of `user.role`.
+    permissions: BTreeSet<Permission>,
+}
+
+impl State {
+    fn open(&mut self) -> Result<&mut Self, ApiError> {
+        if self.closed {
+            return Err(sqlx::Error::PoolClosed.into());
+        }
+        Ok(self)
+    }
+
+    /// The field that another user than `id` already uses, like the unique
+    /// indexes of the SQL backends.
+    fn conflict(
+        &self,
+        id: Option<i64>,
+        username: Option<&UserName>,
+        email: Option<&Email>,
+    ) -> Option<&'static str> {
+        let others = || self.users.values().filter(|row| Some(row.user.id) != id);
+        if let Some(username) = username {
+            let skeleton = username.skeleton();
+            if others()
+                .any(|row| row.user.username == username.get() || row.username_skeleton == skeleton)
+            {
+                return Some("username");
+            }
+        }
+        if let Some(email) = email {
+            let canonical = email.canonical();
+            if others().any(|row| row.user.email == email.get() || row.email_canonical == canonical)
+            {
+                return Some("email");
+            }
+        }
+        None
+    }
+
+    fn find(&self, matches: impl Fn(&User) -> bool) -> Option<&Row> {
+        self.users.values().find(|row| matches(&row.user))
+    }
+
+    fn admins(&self) -> impl Iterator<Item = &Row> {
+        self.users
+            .values()
+            .filter(|row| row.user.role == Role::Admin)
+    }
+
+    fn is_last_admin(&self, id: i64) -> bool {
+        let mut admins = self.admins();
+        admins.next().is_some_and(|row| row.user.id == id) && admins.next().is_none()
+    }
+
+    /// Deletes the user together with their tokens.
+    fn remove_user(&mut self, id: i64) -> Result<(), ApiError> {
+        self.users.remove(&id).ok_or(ApiError::NotFound)?;
+        self.refresh_tokens.retain(|_, (user_id, _)| *user_id != id);
+        self.verification_tokens.retain(|_, user_id| *user_id != id);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryUserRepository {
+    async fn create(
+        &self,
+        username: &UserName,
+        email: &Email,
+        password_hash: &str,
+    ) -> Result<User, ApiError> {
+        let mut state = self.state.lock().unwrap();
+        let state = state.open()?;
+        if let Some(field) = state.conflict(None, Some(username), Some(email)) {
+            return Err(ApiError::Conflict(field.to_string()));
+        }
+        state.last_id += 1;
+        let user = User {
+            id: state.last_id,
+            username: username.get().to_string(),
+            email: email.get().to_string(),
+            email_verified: false,
+            role: Role::User,
+            created_at: format_timestamp(unix_now()),
+        };
+        state.users.insert(
+            user.id,
+            Row {
+                user: user.clone(),
+                username_skeleton: username.skeleton(),
+                email_canonical: email.canonical().to_string(),
+                password_hash: Some(password_hash.to_string()),
+                permissions: BTreeSet::new(),
+            },
+        );
+        Ok(user)
+    }
+
+    async fn get(&self, id: i64) -> Result<User, ApiError> {
+        let mut state = self.state.lock().unwrap();
+        let row = state.open()?.users.get(&id).ok_or(ApiError::NotFound)?;
+        Ok(row.user.clone())
+    }
+
+    async fn get_by_username(&self, username: &str) -> Result<Option<User>, ApiError> {
+        let mut state = self.state.lock().unwrap();
+        let row = state.open()?.find(|user| user.username == username);
+        Ok(row.map(|row| row.user.clone()))
+    }
+
+    async fn get_by_email(&self, email: &str) -> Result<Option<User>, ApiError> {
+        let mut state = self.state.lock().unwrap();
+        let row = state.open()?.find(|user| user.email == email);
+        Ok(row.map(|row| row.user.clone()))
+    }
+
+    async fn password_hash(
+        &self,
+        username: &str,
+    ) -> Result<Option<(i64, Option<String>)>, ApiError> {
+        let mut state = self.state.lock().unwrap();
+        let row = state.open()?.find(|user| user.username == username);
+        Ok(row.map(|row| (row.user.id, row.password_hash.clone())))
+    }
+
+    async fn list(&self, limit: i64, offset: i64) -> Result<(Vec<User>, i64), ApiError> {
+        let mut state = self.state.lock().unwrap();
+        let state = state.open()?;
+        let users = state
+            .users
+            .values()
+            .skip(offset.max(0) as usize)
+            .take(limit.max(0) as usize)
+            .map(|row| row.user.clone())
+            .collect();
+        Ok((users, state.users.len() as i64))
+    }
+
+    async fn update(&self, id: i64, update: &UpdateUser) -> Result<User, ApiError> {
+        let mut state = self.state.lock().unwrap();
+        let state = state.open()?;
+        if !state.users.contains_key(&id) {
+            return Err(ApiError::NotFound);
+        }
+        if let Some(field) =
+            state.conflict(Some(id), update.username.as_ref(), update.email.as_ref())
+        {
+            return Err(ApiError::Conflict(field.to_string()));
+        }
+        let row = state.users.get_mut(&id).ok_or(ApiError::NotFound)?;
+        if let Some(username) = &update.username {
+            row.user.username = username.get().to_string();
+            row.username_skeleton = username.skeleton();
+        }
+        if let Some(email) = &update.email {
+            if row.user.email != email.get() {
+                row.user.email_verified = false;
+            }
+            row.user.email = email.get().to_string();
+            row.email_canonical = email.canonical().to_string();
+        }
+        Ok(row.user.clone())
+    }
+
+    async fn mark_email_verified(&self, id: i64, email: &str) -> Result<User, ApiError> {
+        let mut state = self.state.lock().unwrap();
+        match state.open()?.users.get_mut(&id) {
+            Some(row) if row.user.email == email => {
+                row.user.email_verified = true;
+                Ok(row.user.clone())
+            }
+            _ => Err(ApiError::InvalidToken(
+                "Email address has changed".to_string(),
+            )),
+        }
+    }
+
+    async fn delete(&self, id: i64) -> Result<(), ApiError> {
+        let mut state = self.state.lock().unwrap();
+        state.open()?.remove_user(id)
+    }
+
+    async fn delete_unless_last_admin(&self, id: i64) -> Result<(), ApiError> {
+        let mut state = self.state.lock().unwrap();
+        let state = state.open()?;
+        if state.is_last_admin(id) {
+            return Err(ApiError::LastAdmin);
+        }
+        state.remove_user(id)
+    }
+
+    async fn access(&self, id: i64) -> Result<Access, ApiError> {
+        let mut state = self.state.lock().unwrap();
+        let row = state.open()?.users.get(&id).ok_or(ApiError::NotFound)?;
+        Ok(Access {
+            role: row.user.role,
+            permissions: row.permissions.clone(),
+        })
+    }
+
+    async fn set_access(&self, id: i64, access: &Access) -> Result<(), ApiError> {
+        let mut state = self.state.lock().unwrap();
+        let row = state.open()?.users.get_mut(&id).ok_or(ApiError::NotFound)?;
+        row.user.role = access.role;
+        row.permissions = access.permissions.clone();
+        Ok(())
+    }
+
+    async fn set_access_unless_last_admin(&self, id: i64, access: &Access) -> Result<(), ApiError> {
+        let mut state = self.state.lock().unwrap();
+        let state = state.open()?;
+        if access.role != Role::Admin && state.is_last_admin(id) {
+            return Err(ApiError::LastAdmin);
+        }
+        let row = state.users.get_mut(&id).ok_or(ApiError::NotFound)?;
+        row.user.role = access.role;
+        row.permissions = access.permissions.clone();
+        Ok(())
+    }
+
+    async fn count_admins(&self) -> Result<i64, ApiError> {
+        let mut state = self.state.lock().unwrap();
+        let admins = state.open()?.admins().count();
+        Ok(admins as i64)
+    }
+
+    async fn insert_refresh_token(
+        &self,
+        token_hash: &str,
+        user_id: i64,
+        expires_at: u64,
+    ) -> Result<(), ApiError> {
+        let mut state = self.state.lock().unwrap();
+        state
+            .open()?
+            .refresh_tokens
+            .insert(token_hash.to_string(), (user_id, expires_at as i64));
+        Ok(())
+    }
+
+    async fn take_refresh_token(&self, token_hash: &str) -> Result<Option<(i64, i64)>, ApiError> {
+        let mut state = self.state.lock().unwrap();
+        Ok(state.open()?.refresh_tokens.remove(token_hash))
+    }
+
+    async fn set_verification_token(&self, id: i64, token_hash: &str) -> Result<(), ApiError> {
+        let mut state = self.state.lock().unwrap();
+        let tokens = &mut state.open()?.verification_tokens;
+        tokens.retain(|_, user_id| *user_id != id);
+        tokens.insert(token_hash.to_string(), id);
+        Ok(())
+    }
+
+    async fn take_verification_token(&self, token_hash: &str) -> Result<Option<i64>, ApiError> {
+        let mut state = self.state.lock().unwrap();
+        Ok(state.open()?.verification_tokens.remove(token_hash))
+    }
+
+    async fn delete_expired_refresh_tokens(&self, now: u64) -> Result<u64, ApiError> {
+        let mut state = self.state.lock().unwrap();
+        let tokens = &mut state.open()?.refresh_tokens;
+        let before = tokens.len();
+        tokens.retain(|_, (_, expires_at)| *expires_at > now as i64);
+        Ok((before - tokens.len()) as u64)
+    }
+
+    async fn ping(&self) -> Result<(), ApiError> {
+        self.state.lock().unwrap().open()?;
+        Ok(())
+    }
+
+    async fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+    }
+}
+
+/// Formats unix seconds like SQLite's `CURRENT_TIMESTAMP`, e.g. `2024-11-25 12:00:00`.
+fn format_timestamp(secs: u64) -> String {
+    let (days, time) = (secs / 86_400, secs % 86_400);
+    // civil date from days since 1970-01-01, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60
+    )
+}
+
+#[cfg(test)]
+mod test_memory {
+    use super::*;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(1_732_536_000), "2024-11-25 12:00:00");
+        assert_eq!(format_timestamp(951_825_599), "2000-02-29 11:59:59");
+    }
+}
@@ -0,0 +1,377 @@
+use async_trait::async_trait;
+use sqlx::postgres::{PgConnection, PgPool, PgPoolOptions};
+#[cfg(test)]
+use {sqlx::postgres::PgConnectOptions, std::str::FromStr};
+
+use super::{parse_access, UserRepository};
+use crate::access::{Access, Role};
+use crate::error::ApiError;
+use crate::users::{UpdateUser, User};
+use crate::{Email, UserName};
+
+const USER_COLUMNS: &str =
+    "id, username, email, email_verified_at IS NOT NULL AS email_verified, role, created_at";
+
+/// Users stored in PostgreSQL, see the migrations in `./migrations/postgres`.
+pub struct PostgresUserRepository {
+    pool: PgPool,
+}
+
+impl PostgresUserRepository {
+    /// Connects to the database at `url` and applies all pending migrations.
+    pub async fn connect(url: &str) -> Result<Self, sqlx::Error> {
+        let pool = PgPoolOptions::new().connect(url).await?;
+        sqlx::migrate!("./migrations/postgres").run(&pool).await?;
+        backfill_skeletons(&pool).await?;
+        Ok(Self { pool })
+    }
+
+    /// A fresh schema in the database at `url`, so tests don't see each other's users.
+    #[cfg(test)]
+    pub(super) async fn scratch(url: &str) -> Self {
+        let schema = format!("test_{:016x}", rand::random::<u64>());
+        let admin = PgPoolOptions::new().connect(url).await.unwrap();
+        sqlx::query(&format!("CREATE SCHEMA {schema}"))
+            .execute(&admin)
+            .await
+            .unwrap();
+        let options = PgConnectOptions::from_str(url)
+            .unwrap()
+            .options([("search_path", schema.as_str())]);
+        let pool = PgPoolOptions::new().connect_with(options).await.unwrap();
+        sqlx::migrate!("./migrations/postgres")
+            .run(&pool)
+            .await
+            .unwrap();
+        Self { pool }
+    }
+}
+
+/// Ids of the admins, locked until the end of the transaction so that
+/// concurrent demotions wait and then see each other's writes.
+async fn lock_admins(conn: &mut PgConnection) -> Result<Vec<i64>, sqlx::Error> {
+    sqlx::query_scalar("SELECT id FROM users WHERE role = $1 FOR UPDATE")
+        .bind(Role::Admin.as_str())
+        .fetch_all(conn)
+        .await
+}
+
+/// Replaces the extra permissions of user `id` with those of `access`.
+async fn replace_permissions(
+    conn: &mut PgConnection,
+    id: i64,
+    access: &Access,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM user_permissions WHERE user_id = $1")
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+    for permission in &access.permissions {
+        sqlx::query("INSERT INTO user_permissions (user_id, permission) VALUES ($1, $2)")
+            .bind(id)
+            .bind(permission.as_str())
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Computes the confusable skeleton of users whose skeleton was reset.
+///
+/// Rows that look alike an already stored name keep a NULL skeleton and are logged.
+async fn backfill_skeletons(pool: &PgPool) -> Result<(), sqlx::Error> {
+    let rows: Vec<(i64, String)> =
+        sqlx::query_as("SELECT id, username FROM users WHERE username_skeleton IS NULL")
+            .fetch_all(pool)
+            .await?;
+    for (id, username) in rows {
+        let skeleton = UserName::from_stored(username).skeleton();
+        let result = sqlx::query("UPDATE users SET username_skeleton = $1 WHERE id = $2")
+            .bind(skeleton)
+            .bind(id)
+            .execute(pool)
+            .await;
+        match result {
+            Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+                tracing::warn!("user {id} looks alike another user, skeleton left empty");
+            }
+            result => {
+                result?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl UserRepository for PostgresUserRepository {
+    async fn create(
+        &self,
+        username: &UserName,
+        email: &Email,
+        password_hash: &str,
+    ) -> Result<User, ApiError> {
+        sqlx::query_as(&format!(
+            "INSERT INTO users (username, username_skeleton, email, email_canonical, password_hash) \
+             VALUES ($1, $2, $3, $4, $5) RETURNING {USER_COLUMNS}"
+        ))
+        .bind(username.get())
+        .bind(username.skeleton())
+        .bind(email.get())
+        .bind(email.canonical())
+        .bind(password_hash)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(map_unique_violation)
+    }
+
+    async fn get(&self, id: i64) -> Result<User, ApiError> {
+        sqlx::query_as(&format!("SELECT {USER_COLUMNS} FROM users WHERE id = $1"))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(ApiError::NotFound)
+    }
+
+    async fn get_by_username(&self, username: &str) -> Result<Option<User>, ApiError> {
+        Ok(sqlx::query_as(&format!(
+            "SELECT {USER_COLUMNS} FROM users WHERE username = $1"
+        ))
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn get_by_email(&self, email: &str) -> Result<Option<User>, ApiError> {
+        Ok(sqlx::query_as(&format!(
+            "SELECT {USER_COLUMNS} FROM users WHERE email = $1"
+        ))
+        .bind(email)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn password_hash(
+        &self,
+        username: &str,
+    ) -> Result<Option<(i64, Option<String>)>, ApiError> {
+        Ok(
+            sqlx::query_as("SELECT id, password_hash FROM users WHERE username = $1")
+                .bind(username)
+                .fetch_optional(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn list(&self, limit: i64, offset: i64) -> Result<(Vec<User>, i64), ApiError> {
+        let users = sqlx::query_as(&format!(
+            "SELECT {USER_COLUMNS} FROM users ORDER BY id LIMIT $1 OFFSET $2"
+        ))
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+        let total = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok((users, total))
+    }
+
+    async fn update(&self, id: i64, update: &UpdateUser) -> Result<User, ApiError> {
+        sqlx::query_as(&format!(
+            "UPDATE users SET username = COALESCE($1, username), \
+             username_skeleton = COALESCE($2, username_skeleton), \
+             email_verified_at = CASE WHEN $3::TEXT IS NULL OR $3 = email \
+             THEN email_verified_at END, \
+             email = COALESCE($3, email), email_canonical = COALESCE($4, email_canonical) \
+             WHERE id = $5 RETURNING {USER_COLUMNS}"
+        ))
+        .bind(update.username.as_ref().map(UserName::get))
+        .bind(update.username.as_ref().map(UserName::skeleton))
+        .bind(update.email.as_ref().map(Email::get))
+        .bind(update.email.as_ref().map(Email::canonical))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(map_unique_violation)?
+        .ok_or(ApiError::NotFound)
+    }
+
+    async fn mark_email_verified(&self, id: i64, email: &str) -> Result<User, ApiError> {
+        sqlx::query_as(&format!(
+            "UPDATE users SET email_verified_at = COALESCE(email_verified_at, now()) \
+             WHERE id = $1 AND email = $2 RETURNING {USER_COLUMNS}"
+        ))
+        .bind(id)
+        .bind(email)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| ApiError::InvalidToken("Email address has changed".to_string()))
+    }
+
+    async fn delete(&self, id: i64) -> Result<(), ApiError> {
+        let done = sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        if done.rows_affected() == 0 {
+            return Err(ApiError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn delete_unless_last_admin(&self, id: i64) -> Result<(), ApiError> {
+        let mut tx = self.pool.begin().await?;
+        if lock_admins(&mut tx).await? == [id] {
+            return Err(ApiError::LastAdmin);
+        }
+        let done = sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        if done.rows_affected() == 0 {
+            return Err(ApiError::NotFound);
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn access(&self, id: i64) -> Result<Access, ApiError> {
+        let role: String = sqlx::query_scalar("SELECT role FROM users WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(ApiError::NotFound)?;
+        let permissions =
+            sqlx::query_scalar("SELECT permission FROM user_permissions WHERE user_id = $1")
+                .bind(id)
+                .fetch_all(&self.pool)
+                .await?;
+        parse_access(id, role, permissions)
+    }
+
+    async fn set_access(&self, id: i64, access: &Access) -> Result<(), ApiError> {
+        let mut tx = self.pool.begin().await?;
+        let done = sqlx::query("UPDATE users SET role = $1 WHERE id = $2")
+            .bind(access.role.as_str())
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        if done.rows_affected() == 0 {
+            return Err(ApiError::NotFound);
+        }
+        replace_permissions(&mut tx, id, access).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn set_access_unless_last_admin(&self, id: i64, access: &Access) -> Result<(), ApiError> {
+        let mut tx = self.pool.begin().await?;
+        if access.role != Role::Admin && lock_admins(&mut tx).await? == [id] {
+            return Err(ApiError::LastAdmin);
+        }
+        let done = sqlx::query("UPDATE users SET role = $1 WHERE id = $2")
+            .bind(access.role.as_str())
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        if done.rows_affected() == 0 {
+            return Err(ApiError::NotFound);
+        }
+        replace_permissions(&mut tx, id, access).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn count_admins(&self) -> Result<i64, ApiError> {
+        let admins = sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE role = $1")
+            .bind(Role::Admin.as_str())
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(admins)
+    }
+
+    async fn insert_refresh_token(
+        &self,
+        token_hash: &str,
+        user_id: i64,
+        expires_at: u64,
+    ) -> Result<(), ApiError> {
+        sqlx::query(
+            "INSERT INTO refresh_tokens (token_hash, user_id, expires_at) VALUES ($1, $2, $3)",
+        )
+        .bind(token_hash)
+        .bind(user_id)
+        .bind(expires_at as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn take_refresh_token(&self, token_hash: &str) -> Result<Option<(i64, i64)>, ApiError> {
+        Ok(sqlx::query_as(
+            "DELETE FROM refresh_tokens WHERE token_hash = $1 RETURNING user_id, expires_at",
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn set_verification_token(&self, id: i64, token_hash: &str) -> Result<(), ApiError> {
+        sqlx::query(
+            "INSERT INTO verification_tokens (user_id, token_hash) VALUES ($1, $2) \
+             ON CONFLICT (user_id) DO UPDATE SET token_hash = excluded.token_hash",
+        )
+        .bind(id)
+        .bind(token_hash)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn take_verification_token(&self, token_hash: &str) -> Result<Option<i64>, ApiError> {
+        Ok(sqlx::query_scalar(
+            "DELETE FROM verification_tokens WHERE token_hash = $1 RETURNING user_id",
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn delete_expired_refresh_tokens(&self, now: u64) -> Result<u64, ApiError> {
+        Ok(
+            sqlx::query("DELETE FROM refresh_tokens WHERE expires_at <= $1")
+                .bind(now as i64)
+                .execute(&self.pool)
+                .await?
+                .rows_affected(),
+        )
+    }
+
+    async fn ping(&self) -> Result<(), ApiError> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+fn map_unique_violation(err: sqlx::Error) -> ApiError {
+    match err {
+        sqlx::Error::Database(err) if err.is_unique_violation() => {
+            ApiError::Conflict(unique_field(err.constraint()).to_string())
+        }
+        err => err.into(),
+    }
+}
+
+/// Maps the unique constraints of the `users` table to the request field.
+fn unique_field(constraint: Option<&str>) -> &'static str {
+    match constraint {
+        Some("users_username_key" | "users_username_skeleton_key") => "username",
+        Some("users_email_key" | "users_email_canonical_key") => "email",
+        _ => "user",
+    }
+}
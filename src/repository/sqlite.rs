@@ -0,0 +1,446 @@
+use std::str::FromStr;
+
+use async_trait::async_trait;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteConnection, SqlitePool, SqlitePoolOptions};
+
+use super::{parse_access, UserRepository};
+use crate::access::{Access, Role};
+use crate::error::ApiError;
+use crate::users::{UpdateUser, User};
+use crate::{Email, UserName};
+
+const USER_COLUMNS: &str =
+    "id, username, email, email_verified_at IS NOT NULL AS email_verified, role, created_at";
+
+/// Users stored in SQLite, see the migrations in `./migrations/sqlite`.
+pub struct SqliteUserRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteUserRepository {
+    /// Opens the SQLite database at `url`, creating the file if needed,
+    /// and applies all pending migrations.
+    pub async fn connect(url: &str) -> Result<Self, sqlx::Error> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        let pool = SqlitePoolOptions::new().connect_with(options).await?;
+        sqlx::migrate!("./migrations/sqlite").run(&pool).await?;
+        backfill_skeletons(&pool).await?;
+        backfill_canonical_emails(&pool).await?;
+        Ok(Self { pool })
+    }
+
+    /// Why a write guarded against removing the last admin changed no row.
+    async fn missing_or_last_admin(&self, id: i64) -> ApiError {
+        match self.access(id).await {
+            Ok(_) => ApiError::LastAdmin,
+            Err(err) => err,
+        }
+    }
+
+    /// A fresh in-memory database.
+    #[cfg(test)]
+    pub(super) async fn memory() -> Self {
+        Self {
+            pool: memory_pool().await,
+        }
+    }
+}
+
+/// Computes the confusable skeleton of users stored before it existed.
+///
+/// Rows that look alike an already stored name keep a NULL skeleton and are logged.
+async fn backfill_skeletons(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let rows: Vec<(i64, String)> =
+        sqlx::query_as("SELECT id, username FROM users WHERE username_skeleton IS NULL")
+            .fetch_all(pool)
+            .await?;
+    for (id, username) in rows {
+        let skeleton = UserName::from_stored(username).skeleton();
+        let result = sqlx::query("UPDATE users SET username_skeleton = ? WHERE id = ?")
+            .bind(skeleton)
+            .bind(id)
+            .execute(pool)
+            .await;
+        match result {
+            Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+                tracing::warn!("user {id} looks alike another user, skeleton left empty");
+            }
+            result => {
+                result?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Computes the canonical email of users stored before it existed.
+///
+/// Rows whose address duplicates an already stored one keep a NULL value and are logged.
+async fn backfill_canonical_emails(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let rows: Vec<(i64, String)> =
+        sqlx::query_as("SELECT id, email FROM users WHERE email_canonical IS NULL")
+            .fetch_all(pool)
+            .await?;
+    for (id, email) in rows {
+        let result = sqlx::query("UPDATE users SET email_canonical = ? WHERE id = ?")
+            .bind(Email::from_stored(email).canonical())
+            .bind(id)
+            .execute(pool)
+            .await;
+        match result {
+            Err(sqlx::Error::Database(err)) if err.is_unique_violation() => {
+                tracing::warn!("user {id} shares an email with another user, left empty");
+            }
+            result => {
+                result?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl UserRepository for SqliteUserRepository {
+    async fn create(
+        &self,
+        username: &UserName,
+        email: &Email,
+        password_hash: &str,
+    ) -> Result<User, ApiError> {
+        sqlx::query_as(&format!(
+            "INSERT INTO users (username, username_skeleton, email, email_canonical, password_hash) \
+             VALUES (?, ?, ?, ?, ?) RETURNING {USER_COLUMNS}"
+        ))
+        .bind(username.get())
+        .bind(username.skeleton())
+        .bind(email.get())
+        .bind(email.canonical())
+        .bind(password_hash)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(map_unique_violation)
+    }
+
+    async fn get(&self, id: i64) -> Result<User, ApiError> {
+        sqlx::query_as(&format!("SELECT {USER_COLUMNS} FROM users WHERE id = ?"))
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(ApiError::NotFound)
+    }
+
+    async fn get_by_username(&self, username: &str) -> Result<Option<User>, ApiError> {
+        Ok(sqlx::query_as(&format!(
+            "SELECT {USER_COLUMNS} FROM users WHERE username = ?"
+        ))
+        .bind(username)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn get_by_email(&self, email: &str) -> Result<Option<User>, ApiError> {
+        Ok(
+            sqlx::query_as(&format!("SELECT {USER_COLUMNS} FROM users WHERE email = ?"))
+                .bind(email)
+                .fetch_optional(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn password_hash(
+        &self,
+        username: &str,
+    ) -> Result<Option<(i64, Option<String>)>, ApiError> {
+        Ok(
+            sqlx::query_as("SELECT id, password_hash FROM users WHERE username = ?")
+                .bind(username)
+                .fetch_optional(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn list(&self, limit: i64, offset: i64) -> Result<(Vec<User>, i64), ApiError> {
+        let users = sqlx::query_as(&format!(
+            "SELECT {USER_COLUMNS} FROM users ORDER BY id LIMIT ? OFFSET ?"
+        ))
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await?;
+        let total = sqlx::query_scalar("SELECT COUNT(*) FROM users")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok((users, total))
+    }
+
+    async fn update(&self, id: i64, update: &UpdateUser) -> Result<User, ApiError> {
+        sqlx::query_as(&format!(
+            "UPDATE users SET username = COALESCE(?, username), \
+             username_skeleton = COALESCE(?, username_skeleton), \
+             email_verified_at = CASE WHEN ? IS NULL OR ? = email THEN email_verified_at END, \
+             email = COALESCE(?, email), email_canonical = COALESCE(?, email_canonical) \
+             WHERE id = ? RETURNING {USER_COLUMNS}"
+        ))
+        .bind(update.username.as_ref().map(UserName::get))
+        .bind(update.username.as_ref().map(UserName::skeleton))
+        .bind(update.email.as_ref().map(Email::get))
+        .bind(update.email.as_ref().map(Email::get))
+        .bind(update.email.as_ref().map(Email::get))
+        .bind(update.email.as_ref().map(Email::canonical))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(map_unique_violation)?
+        .ok_or(ApiError::NotFound)
+    }
+
+    async fn mark_email_verified(&self, id: i64, email: &str) -> Result<User, ApiError> {
+        sqlx::query_as(&format!(
+            "UPDATE users SET email_verified_at = COALESCE(email_verified_at, CURRENT_TIMESTAMP) \
+             WHERE id = ? AND email = ? RETURNING {USER_COLUMNS}"
+        ))
+        .bind(id)
+        .bind(email)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or_else(|| ApiError::InvalidToken("Email address has changed".to_string()))
+    }
+
+    async fn delete(&self, id: i64) -> Result<(), ApiError> {
+        // refresh tokens go with the user through `ON DELETE CASCADE`
+        let done = sqlx::query("DELETE FROM users WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        if done.rows_affected() == 0 {
+            return Err(ApiError::NotFound);
+        }
+        Ok(())
+    }
+
+    async fn delete_unless_last_admin(&self, id: i64) -> Result<(), ApiError> {
+        let done = sqlx::query(
+            "DELETE FROM users WHERE id = ?1 \
+             AND (role <> ?2 OR (SELECT COUNT(*) FROM users WHERE role = ?2) > 1)",
+        )
+        .bind(id)
+        .bind(Role::Admin.as_str())
+        .execute(&self.pool)
+        .await?;
+        if done.rows_affected() == 0 {
+            return Err(self.missing_or_last_admin(id).await);
+        }
+        Ok(())
+    }
+
+    async fn access(&self, id: i64) -> Result<Access, ApiError> {
+        let role: String = sqlx::query_scalar("SELECT role FROM users WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(ApiError::NotFound)?;
+        let permissions =
+            sqlx::query_scalar("SELECT permission FROM user_permissions WHERE user_id = ?")
+                .bind(id)
+                .fetch_all(&self.pool)
+                .await?;
+        parse_access(id, role, permissions)
+    }
+
+    async fn set_access(&self, id: i64, access: &Access) -> Result<(), ApiError> {
+        let mut tx = self.pool.begin().await?;
+        let done = sqlx::query("UPDATE users SET role = ? WHERE id = ?")
+            .bind(access.role.as_str())
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        if done.rows_affected() == 0 {
+            return Err(ApiError::NotFound);
+        }
+        replace_permissions(&mut tx, id, access).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn set_access_unless_last_admin(&self, id: i64, access: &Access) -> Result<(), ApiError> {
+        let mut tx = self.pool.begin().await?;
+        // one statement, so the count can't change before the write
+        let done = sqlx::query(
+            "UPDATE users SET role = ?1 WHERE id = ?2 \
+             AND (?1 = ?3 OR role <> ?3 OR (SELECT COUNT(*) FROM users WHERE role = ?3) > 1)",
+        )
+        .bind(access.role.as_str())
+        .bind(id)
+        .bind(Role::Admin.as_str())
+        .execute(&mut *tx)
+        .await?;
+        if done.rows_affected() == 0 {
+            // frees the connection for the lookup
+            tx.rollback().await?;
+            return Err(self.missing_or_last_admin(id).await);
+        }
+        replace_permissions(&mut tx, id, access).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn count_admins(&self) -> Result<i64, ApiError> {
+        let admins = sqlx::query_scalar("SELECT COUNT(*) FROM users WHERE role = ?")
+            .bind(Role::Admin.as_str())
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(admins)
+    }
+
+    async fn insert_refresh_token(
+        &self,
+        token_hash: &str,
+        user_id: i64,
+        expires_at: u64,
+    ) -> Result<(), ApiError> {
+        sqlx::query(
+            "INSERT INTO refresh_tokens (token_hash, user_id, expires_at) VALUES (?, ?, ?)",
+        )
+        .bind(token_hash)
+        .bind(user_id)
+        .bind(expires_at as i64)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn take_refresh_token(&self, token_hash: &str) -> Result<Option<(i64, i64)>, ApiError> {
+        Ok(sqlx::query_as(
+            "DELETE FROM refresh_tokens WHERE token_hash = ? RETURNING user_id, expires_at",
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn set_verification_token(&self, id: i64, token_hash: &str) -> Result<(), ApiError> {
+        sqlx::query(
+            "INSERT INTO verification_tokens (user_id, token_hash) VALUES (?, ?) \
+             ON CONFLICT (user_id) DO UPDATE SET token_hash = excluded.token_hash",
+        )
+        .bind(id)
+        .bind(token_hash)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn take_verification_token(&self, token_hash: &str) -> Result<Option<i64>, ApiError> {
+        Ok(sqlx::query_scalar(
+            "DELETE FROM verification_tokens WHERE token_hash = ? RETURNING user_id",
+        )
+        .bind(token_hash)
+        .fetch_optional(&self.pool)
+        .await?)
+    }
+
+    async fn delete_expired_refresh_tokens(&self, now: u64) -> Result<u64, ApiError> {
+        Ok(
+            sqlx::query("DELETE FROM refresh_tokens WHERE expires_at <= ?")
+                .bind(now as i64)
+                .execute(&self.pool)
+                .await?
+                .rows_affected(),
+        )
+    }
+
+    async fn ping(&self) -> Result<(), ApiError> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn close(&self) {
+        self.pool.close().await;
+    }
+}
+
+/// Replaces the extra permissions of user `id` with those of `access`.
+async fn replace_permissions(
+    conn: &mut SqliteConnection,
+    id: i64,
+    access: &Access,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM user_permissions WHERE user_id = ?")
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+    for permission in &access.permissions {
+        sqlx::query("INSERT INTO user_permissions (user_id, permission) VALUES (?, ?)")
+            .bind(id)
+            .bind(permission.as_str())
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+fn map_unique_violation(err: sqlx::Error) -> ApiError {
+    match err {
+        sqlx::Error::Database(err) if err.is_unique_violation() => {
+            ApiError::Conflict(unique_field(err.message()).to_string())
+        }
+        err => err.into(),
+    }
+}
+
+/// Extracts the offending column from a SQLite
+/// `UNIQUE constraint failed: users.<column>` message.
+fn unique_field(message: &str) -> &str {
+    match message.rsplit_once("users.").map(|(_, field)| field) {
+        Some("username_skeleton") => "username",
+        Some("email_canonical") => "email",
+        Some(field) => field,
+        None => "user",
+    }
+}
+
+#[cfg(test)]
+async fn memory_pool() -> SqlitePool {
+    let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap();
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await
+        .unwrap();
+    sqlx::migrate!("./migrations/sqlite")
+        .run(&pool)
+        .await
+        .unwrap();
+    pool
+}
+
+#[cfg(test)]
+mod test_sqlite {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_backfill_skeletons() {
+        let pool = memory_pool().await;
+        sqlx::query("INSERT INTO users (username, email) VALUES ('HelloWorldIAmTim', 'a@b.c')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        backfill_skeletons(&pool).await.unwrap();
+        backfill_canonical_emails(&pool).await.unwrap();
+        let canonical: Option<String> = sqlx::query_scalar("SELECT email_canonical FROM users")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(canonical.unwrap(), "a@b.c");
+        let skeleton: Option<String> = sqlx::query_scalar("SELECT username_skeleton FROM users")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(
+            skeleton.unwrap(),
+            UserName::try_new("HelloWorldIAmTim".to_string())
+                .unwrap()
+                .skeleton()
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::{Request, State};
+use axum::http::header::CONNECTION;
+use axum::http::{HeaderValue, Version};
+use axum::middleware::Next;
+use axum::response::Response;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use crate::repository::UserRepository;
+use crate::token::unix_now;
+
+const REFRESH_TOKEN_CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Readiness of the server and the background tasks that have to stop with it.
+#[derive(Clone)]
+pub struct Lifecycle {
+    ready: Arc<AtomicBool>,
+    cancel: CancellationToken,
+    tasks: TaskTracker,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self {
+            ready: Arc::new(AtomicBool::new(true)),
+            cancel: CancellationToken::new(),
+            tasks: TaskTracker::new(),
+        }
+    }
+}
+
+impl Lifecycle {
+    /// Whether the server accepts new work; false once shutdown has begun.
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    pub fn set_not_ready(&self) {
+        self.ready.store(false, Ordering::Relaxed);
+    }
+
+    /// Runs a background task; it has to return once the token is cancelled.
+    pub fn spawn<F, Fut>(&self, task: F)
+    where
+        F: FnOnce(CancellationToken) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(task(self.cancel.clone()));
+    }
+
+    /// Cancels all background tasks and waits until they have finished.
+    pub async fn stop_tasks(&self) {
+        self.cancel.cancel();
+        self.tasks.close();
+        self.tasks.wait().await;
+    }
+}
+
+/// Completes on SIGINT (Ctrl+C) or, on Unix, SIGTERM.
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!("failed to listen for Ctrl+C: {err}");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut stream) => {
+                stream.recv().await;
+            }
+            Err(err) => {
+                tracing::error!("failed to listen for SIGTERM: {err}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+}
+
+/// Asks keep-alive clients to reconnect, ideally to another instance,
+/// while this one is shutting down.
+pub async fn close_when_not_ready(
+    State(lifecycle): State<Lifecycle>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // `Connection` is not allowed in HTTP/2, which has GOAWAY for this instead
+    let http1 = request.version() < Version::HTTP_2;
+    let mut response = next.run(request).await;
+    if http1 && !lifecycle.is_ready() {
+        response
+            .headers_mut()
+            .insert(CONNECTION, HeaderValue::from_static("close"));
+    }
+    response
+}
+
+/// Periodically deletes expired refresh tokens until `cancel` is triggered.
+pub async fn clean_refresh_tokens(users: Arc<dyn UserRepository>, cancel: CancellationToken) {
+    let mut interval = tokio::time::interval(REFRESH_TOKEN_CLEANUP_INTERVAL);
+    loop {
+        tokio::select! {
+            () = cancel.cancelled() => return,
+            _ = interval.tick() => {}
+        }
+        match users.delete_expired_refresh_tokens(unix_now()).await {
+            Ok(0) => {}
+            Ok(count) => tracing::debug!("deleted {count} expired refresh tokens"),
+            Err(err) => tracing::warn!("failed to delete expired refresh tokens: {err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_shutdown {
+    use super::*;
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_stop_tasks() {
+        let lifecycle = Lifecycle::default();
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        lifecycle.spawn(|cancel| async move {
+            cancel.cancelled().await;
+            tx.send(()).unwrap();
+        });
+        tokio::time::timeout(Duration::from_secs(1), lifecycle.stop_tasks())
+            .await
+            .unwrap();
+        assert!(rx.await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_connection_close() {
+        let lifecycle = Lifecycle::default();
+        let app = Router::new()
+            .route("/", get(|| async {}))
+            .layer(middleware::from_fn_with_state(
+                lifecycle.clone(),
+                close_when_not_ready,
+            ));
+        let request = || {
+            Request::builder()
+                .uri("/")
+                .body(axum::body::Body::empty())
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(request()).await.unwrap();
+        assert!(response.headers().get(CONNECTION).is_none());
+
+        lifecycle.set_not_ready();
+        assert!(!lifecycle.is_ready());
+        let response = app.oneshot(request()).await.unwrap();
+        assert_eq!(response.headers()[CONNECTION], "close");
+    }
+}
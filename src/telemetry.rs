@@ -0,0 +1,370 @@
+use std::time::{Duration, Instant};
+
+use axum::extract::{MatchedPath, Request};
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::{TraceError, TracerProvider as _};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{Protocol, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::{runtime, Resource};
+use tracing::field::Empty;
+use tracing::{Instrument, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use crate::config::{LogFormat, LogSettings, OtlpProtocol, OtlpSettings};
+use crate::error::StartupError;
+use crate::metrics::UNMATCHED;
+
+/// Correlates a request across services; taken from the client if present.
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest client supplied request id that is accepted as is.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Flushes spans that have not been exported yet when shut down.
+pub struct Telemetry {
+    provider: Option<TracerProvider>,
+}
+
+impl Telemetry {
+    pub fn shutdown(self) {
+        if let Some(Err(err)) = self.provider.map(|provider| provider.shutdown()) {
+            tracing::warn!("failed to flush spans: {err}");
+        }
+    }
+}
+
+/// Installs the global subscriber writing logs in the configured format and,
+/// if a collector is configured, exporting spans over OTLP.
+pub fn init(log: &LogSettings, otlp: &OtlpSettings) -> Result<Telemetry, StartupError> {
+    let filter = EnvFilter::try_new(&log.level)?;
+    let fmt = tracing_subscriber::fmt::layer();
+    let fmt = match log.format {
+        LogFormat::Full => fmt.boxed(),
+        LogFormat::Pretty => fmt.pretty().boxed(),
+        LogFormat::Json => fmt.json().flatten_event(true).with_span_list(false).boxed(),
+    };
+    let provider = otlp
+        .endpoint
+        .as_deref()
+        .map(|endpoint| tracer_provider(endpoint, otlp))
+        .transpose()?;
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt)
+        .with(provider.as_ref().map(otel_layer))
+        .init();
+    Ok(Telemetry { provider })
+}
+
+fn tracer_provider(endpoint: &str, settings: &OtlpSettings) -> Result<TracerProvider, TraceError> {
+    let timeout = Duration::from_secs(settings.timeout_secs);
+    let exporter = match settings.protocol {
+        OtlpProtocol::Grpc => SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(endpoint)
+            .with_timeout(timeout)
+            .build()?,
+        OtlpProtocol::Http => SpanExporter::builder()
+            .with_http()
+            .with_protocol(Protocol::HttpBinary)
+            .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+            .with_timeout(timeout)
+            .build()?,
+    };
+    Ok(TracerProvider::builder()
+        .with_batch_exporter(exporter, runtime::Tokio)
+        .with_resource(Resource::new([KeyValue::new(
+            "service.name",
+            settings.service_name.clone(),
+        )]))
+        .build())
+}
+
+fn otel_layer<S>(provider: &TracerProvider) -> impl Layer<S>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+}
+
+/// Reads W3C `traceparent`/`tracestate` from request headers.
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+/// The id of the request the current task is handling, if any.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// Runs every request in a span with method, route, status and latency, and
+/// echoes its `X-Request-Id`, generating one if the client did not send any.
+///
+/// The span continues the trace of an incoming W3C `traceparent` header.
+pub async fn trace_request(request: Request, next: Next) -> Response {
+    let request_id = request
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map_or_else(generate_request_id, str::to_string);
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        // a fixed name keeps 404 probes from creating a span name each
+        .map_or(UNMATCHED, MatchedPath::as_str)
+        .to_string();
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        route,
+        request_id,
+        status = Empty,
+        latency_ms = Empty,
+        otel.name = format!("{} {route}", request.method()),
+        otel.kind = "server",
+        otel.status_code = Empty,
+    );
+    span.set_parent(TraceContextPropagator::new().extract(&HeaderExtractor(request.headers())));
+
+    let start = Instant::now();
+    let mut response = REQUEST_ID
+        .scope(request_id.clone(), next.run(request))
+        .instrument(span.clone())
+        .await;
+    let status = response.status();
+    span.record("status", status.as_u16());
+    span.record("latency_ms", start.elapsed().as_millis() as u64);
+    span.in_scope(|| {
+        if status.is_server_error() {
+            span.record("otel.status_code", "error");
+            tracing::warn!("request failed");
+        } else {
+            tracing::info!("request finished");
+        }
+    });
+
+    // only visible ASCII passes `is_valid_request_id` and generation
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(X_REQUEST_ID, value);
+    }
+    response
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LENGTH
+        && id.bytes().all(|byte| byte.is_ascii_graphic())
+}
+
+fn generate_request_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+#[cfg(test)]
+mod test_telemetry {
+    use super::*;
+    use crate::error::ApiError;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        Router::new()
+            .route("/missing", get(|| async { ApiError::NotFound }))
+            .layer(middleware::from_fn(trace_request))
+    }
+
+    async fn send(request_id: Option<&str>) -> Response {
+        let mut request = Request::builder().uri("/missing");
+        if let Some(id) = request_id {
+            request = request.header(&X_REQUEST_ID, id);
+        }
+        app()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    async fn body_request_id(response: Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap()["request_id"].clone()
+    }
+
+    #[tokio::test]
+    async fn test_propagates_request_id() {
+        let response = send(Some("abc-123")).await;
+        assert_eq!(response.headers()[&X_REQUEST_ID], "abc-123");
+        assert_eq!(body_request_id(response).await, "abc-123");
+    }
+
+    #[tokio::test]
+    async fn test_generates_request_id() {
+        for sent in [None, Some("not valid"), Some(&*"x".repeat(200))] {
+            let response = send(sent).await;
+            let id = response.headers()[&X_REQUEST_ID]
+                .to_str()
+                .unwrap()
+                .to_string();
+            assert_eq!(id.len(), 32);
+            assert_eq!(body_request_id(response).await, id);
+        }
+    }
+
+    #[test]
+    fn test_no_request_outside_of_requests() {
+        assert_eq!(current_request_id(), None);
+    }
+}
+
+#[cfg(test)]
+mod test_otlp {
+    use super::*;
+    use axum::body::{Body, Bytes};
+    use axum::routing::{get, post};
+    use axum::{middleware, Router};
+    use opentelemetry_proto::tonic::collector::trace::v1::trace_service_server::{
+        TraceService, TraceServiceServer,
+    };
+    use opentelemetry_proto::tonic::collector::trace::v1::{
+        ExportTraceServiceRequest, ExportTraceServiceResponse,
+    };
+    use opentelemetry_proto::tonic::trace::v1::Span;
+    use prost::Message;
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+    use tower::ServiceExt;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const PARENT_ID: &str = "00f067aa0ba902b7";
+
+    /// Stands in for an OpenTelemetry collector's gRPC trace service.
+    struct GrpcCollector(UnboundedSender<ExportTraceServiceRequest>);
+
+    #[tonic::async_trait]
+    impl TraceService for GrpcCollector {
+        async fn export(
+            &self,
+            request: tonic::Request<ExportTraceServiceRequest>,
+        ) -> Result<tonic::Response<ExportTraceServiceResponse>, tonic::Status> {
+            self.0.send(request.into_inner()).unwrap();
+            Ok(tonic::Response::new(ExportTraceServiceResponse::default()))
+        }
+    }
+
+    /// Starts a collector stand-in and returns its endpoint.
+    async fn collector(
+        protocol: OtlpProtocol,
+    ) -> (String, UnboundedReceiver<ExportTraceServiceRequest>) {
+        let (tx, rx) = unbounded_channel();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        match protocol {
+            OtlpProtocol::Grpc => {
+                let service = TraceServiceServer::new(GrpcCollector(tx));
+                let incoming = tokio_stream::wrappers::TcpListenerStream::new(listener);
+                tokio::spawn(
+                    tonic::transport::Server::builder()
+                        .add_service(service)
+                        .serve_with_incoming(incoming),
+                );
+            }
+            OtlpProtocol::Http => {
+                let app = Router::new().route(
+                    "/v1/traces",
+                    post(move |body: Bytes| async move {
+                        tx.send(ExportTraceServiceRequest::decode(body).unwrap())
+                            .unwrap();
+                    }),
+                );
+                tokio::spawn(async move { axum::serve(listener, app).await });
+            }
+        }
+        (endpoint, rx)
+    }
+
+    async fn exported_request_span(protocol: OtlpProtocol, uri: &str) -> Span {
+        let (endpoint, mut received) = collector(protocol).await;
+        let settings = OtlpSettings {
+            endpoint: Some(endpoint.clone()),
+            protocol,
+            ..OtlpSettings::default()
+        };
+        let provider = tracer_provider(&endpoint, &settings).unwrap();
+        let subscriber = tracing_subscriber::registry().with(otel_layer(&provider));
+        let app = Router::new()
+            .route("/users/:id", get(|| async {}))
+            .layer(middleware::from_fn(trace_request));
+        let request = Request::builder()
+            .uri(uri)
+            .header("traceparent", format!("00-{TRACE_ID}-{PARENT_ID}-01"))
+            .body(Body::empty())
+            .unwrap();
+        {
+            // a multi-threaded runtime drives the test future on this thread
+            let _guard = tracing::subscriber::set_default(subscriber);
+            app.oneshot(request).await.unwrap();
+        }
+        tokio::task::spawn_blocking(move || provider.shutdown())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let request = received.recv().await.unwrap();
+        let resource = request.resource_spans[0].resource.clone().unwrap();
+        assert!(resource
+            .attributes
+            .iter()
+            .any(|attribute| attribute.key == "service.name"));
+        request.resource_spans[0].scope_spans[0].spans[0].clone()
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_grpc_export() {
+        let span = exported_request_span(OtlpProtocol::Grpc, "/users/1").await;
+        assert_eq!(span.name, "GET /users/:id");
+        assert_eq!(hex(&span.trace_id), TRACE_ID);
+        assert_eq!(hex(&span.parent_span_id), PARENT_ID);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_http_export() {
+        let span = exported_request_span(OtlpProtocol::Http, "/users/1").await;
+        assert_eq!(span.name, "GET /users/:id");
+        assert_eq!(hex(&span.trace_id), TRACE_ID);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_unmatched_route() {
+        let span = exported_request_span(OtlpProtocol::Http, "/wp-login.php").await;
+        assert_eq!(span.name, "GET unmatched");
+    }
+}
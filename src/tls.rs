@@ -0,0 +1,666 @@
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::uri::{Authority, PathAndQuery};
+use axum::http::{header::HOST, HeaderMap, StatusCode, Uri};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::Router;
+use hyper::body::Incoming;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::danger::ClientCertVerifier;
+use rustls::server::{ClientHello, ResolvesServerCert, VerifierBuilderError, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+use thiserror::Error;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tower::ServiceExt;
+
+use crate::client_cert::ClientCert;
+use crate::config::TlsSettings;
+
+/// Connections that have not completed the handshake by then are dropped.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("server.tls needs both cert_file and key_file")]
+    Incomplete,
+    #[error("failed to read {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("invalid PEM in {path}: {source}")]
+    Pem {
+        path: PathBuf,
+        source: rustls::pki_types::pem::Error,
+    },
+    #[error("no certificate in {0}")]
+    NoCertificate(PathBuf),
+    #[error("server.tls.require_client_cert needs client_ca_file")]
+    MissingClientCa,
+    /// Without client certificates every client route would answer 403.
+    #[error("server.tls.client_routes needs cert_file, key_file and client_ca_file")]
+    ClientRoutesWithoutMtls,
+    #[error("invalid client CA: {0}")]
+    ClientCa(#[from] VerifierBuilderError),
+    #[error("{0}")]
+    Rustls(#[from] rustls::Error),
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+/// Serves the certificate from `cert_file` and `key_file` and swaps it when
+/// [`reload`](Self::reload) finds that the files changed.
+pub struct CertResolver {
+    cert_file: PathBuf,
+    key_file: PathBuf,
+    /// File contents the current key was loaded from.
+    loaded: Mutex<(Vec<u8>, Vec<u8>)>,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl std::fmt::Debug for CertResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CertResolver")
+            .field("cert_file", &self.cert_file)
+            .field("key_file", &self.key_file)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CertResolver {
+    pub fn new(cert_file: PathBuf, key_file: PathBuf) -> Result<Self, TlsError> {
+        let (cert_pem, key_pem) = (read(&cert_file)?, read(&key_file)?);
+        let key = certified_key(&cert_file, &cert_pem, &key_file, &key_pem)?;
+        Ok(Self {
+            cert_file,
+            key_file,
+            loaded: Mutex::new((cert_pem, key_pem)),
+            current: RwLock::new(Arc::new(key)),
+        })
+    }
+
+    /// The resolver for `server.tls`, or `None` when TLS is not configured.
+    pub fn from_settings(settings: &TlsSettings) -> Result<Option<Self>, TlsError> {
+        let mtls = settings.cert_file.is_some() && settings.client_ca_file.is_some();
+        if !settings.client_routes.is_empty() && !mtls {
+            return Err(TlsError::ClientRoutesWithoutMtls);
+        }
+        match (&settings.cert_file, &settings.key_file) {
+            (Some(cert), Some(key)) => Self::new(cert.clone(), key.clone()).map(Some),
+            (None, None) => Ok(None),
+            _ => Err(TlsError::Incomplete),
+        }
+    }
+
+    /// Loads the files again if their content changed; returns whether the
+    /// certificate was replaced. On errors the previous certificate stays in use.
+    pub fn reload(&self) -> Result<bool, TlsError> {
+        let (cert_pem, key_pem) = (read(&self.cert_file)?, read(&self.key_file)?);
+        let mut loaded = self.loaded.lock().unwrap();
+        if loaded.0 == cert_pem && loaded.1 == key_pem {
+            return Ok(false);
+        }
+        let key = certified_key(&self.cert_file, &cert_pem, &self.key_file, &key_pem)?;
+        *self.current.write().unwrap() = Arc::new(key);
+        *loaded = (cert_pem, key_pem);
+        Ok(true)
+    }
+
+    /// Checks for changed files every `interval` until `cancel` is triggered.
+    pub async fn watch(self: Arc<Self>, interval: Duration, cancel: CancellationToken) {
+        let mut interval = tokio::time::interval(interval);
+        interval.tick().await;
+        loop {
+            tokio::select! {
+                () = cancel.cancelled() => return,
+                _ = interval.tick() => {}
+            }
+            match self.reload() {
+                Ok(true) => tracing::info!("reloaded TLS certificate {}", self.cert_file.display()),
+                Ok(false) => {}
+                Err(err) => tracing::warn!("keeping the current TLS certificate: {err}"),
+            }
+        }
+    }
+
+    /// Server config that negotiates HTTP/2 or HTTP/1.1 through ALPN and
+    /// verifies client certificates if `settings` name a client CA.
+    pub fn server_config(
+        self: Arc<Self>,
+        settings: &TlsSettings,
+    ) -> Result<ServerConfig, TlsError> {
+        let builder = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()?;
+        let builder = match client_verifier(settings)? {
+            Some(verifier) => builder.with_client_cert_verifier(verifier),
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder.with_cert_resolver(self);
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(config)
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, TlsError> {
+    std::fs::read(path).map_err(|source| TlsError::Read {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+fn pem_certificates(path: &Path, pem: &[u8]) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    let certs = CertificateDer::pem_slice_iter(pem)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|source| TlsError::Pem {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificate(path.to_path_buf()));
+    }
+    Ok(certs)
+}
+
+fn client_verifier(
+    settings: &TlsSettings,
+) -> Result<Option<Arc<dyn ClientCertVerifier>>, TlsError> {
+    let Some(ca_file) = &settings.client_ca_file else {
+        if settings.require_client_cert {
+            return Err(TlsError::MissingClientCa);
+        }
+        return Ok(None);
+    };
+    let mut roots = RootCertStore::empty();
+    for cert in pem_certificates(ca_file, &read(ca_file)?)? {
+        roots.add(cert)?;
+    }
+    let mut builder = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider());
+    if !settings.require_client_cert {
+        builder = builder.allow_unauthenticated();
+    }
+    Ok(Some(builder.build()?))
+}
+
+fn certified_key(
+    cert_file: &Path,
+    cert_pem: &[u8],
+    key_file: &Path,
+    key_pem: &[u8],
+) -> Result<CertifiedKey, TlsError> {
+    let chain = pem_certificates(cert_file, cert_pem)?;
+    let key = PrivateKeyDer::from_pem_slice(key_pem).map_err(|source| TlsError::Pem {
+        path: key_file.to_path_buf(),
+        source,
+    })?;
+    let key = CertifiedKey::new(chain, provider().key_provider.load_private_key(key)?);
+    key.keys_match()?;
+    Ok(key)
+}
+
+/// Serves `app` over TLS until `signal` completes, then lets open connections
+/// finish their requests.
+///
+/// Requests carry the peer address as `ConnectInfo<SocketAddr>` and a verified
+/// client certificate as [`ClientCert`].
+pub async fn serve(
+    listener: TcpListener,
+    config: Arc<ServerConfig>,
+    app: Router,
+    signal: impl Future<Output = ()>,
+) -> std::io::Result<()> {
+    let acceptor = TlsAcceptor::from(config);
+    let connections = TaskTracker::new();
+    let closing = CancellationToken::new();
+    tokio::pin!(signal);
+    loop {
+        let (stream, peer) = tokio::select! {
+            () = &mut signal => break,
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    // e.g. too many open files; give the process a moment to recover
+                    tracing::warn!("failed to accept connection: {err}");
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+        };
+        let (acceptor, app, closing) = (acceptor.clone(), app.clone(), closing.clone());
+        connections.spawn(async move {
+            let stream = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+                .await
+            {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(err)) => return tracing::debug!("TLS handshake with {peer} failed: {err}"),
+                Err(_) => return tracing::debug!("TLS handshake with {peer} timed out"),
+            };
+            let client = stream
+                .get_ref()
+                .1
+                .peer_certificates()
+                .and_then(|chain| chain.first())
+                .and_then(|leaf| ClientCert::from_der(leaf));
+            let service = hyper::service::service_fn(move |mut request: Request<Incoming>| {
+                request.extensions_mut().insert(ConnectInfo(peer));
+                if let Some(client) = &client {
+                    request.extensions_mut().insert(client.clone());
+                }
+                app.clone().oneshot(request)
+            });
+            let builder = auto::Builder::new(TokioExecutor::new());
+            let connection = builder.serve_connection_with_upgrades(TokioIo::new(stream), service);
+            tokio::pin!(connection);
+            let result = tokio::select! {
+                result = connection.as_mut() => result,
+                () = closing.cancelled() => {
+                    connection.as_mut().graceful_shutdown();
+                    connection.await
+                }
+            };
+            if let Err(err) = result {
+                tracing::debug!("connection with {peer} failed: {err}");
+            }
+        });
+    }
+    drop(listener);
+    closing.cancel();
+    connections.close();
+    connections.wait().await;
+    Ok(())
+}
+
+/// Redirects every request to the same URL on HTTPS at `https_port`.
+pub fn redirect_router(https_port: u16) -> Router {
+    Router::new()
+        .fallback(redirect_to_https)
+        .with_state(https_port)
+}
+
+async fn redirect_to_https(
+    State(https_port): State<u16>,
+    headers: HeaderMap,
+    uri: Uri,
+) -> Response {
+    let authority = uri
+        .authority()
+        .map(Authority::as_str)
+        .or_else(|| headers.get(HOST).and_then(|host| host.to_str().ok()));
+    let Some(host) = authority.and_then(|authority| authority.parse::<Authority>().ok()) else {
+        return (StatusCode::BAD_REQUEST, "Missing Host header").into_response();
+    };
+    let port = match https_port {
+        443 => String::new(),
+        port => format!(":{port}"),
+    };
+    let path = uri.path_and_query().map_or("/", PathAndQuery::as_str);
+    Redirect::permanent(&format!("https://{}{port}{path}", host.host())).into_response()
+}
+
+/// Serves [`redirect_router`] until `cancel` is triggered.
+pub async fn serve_redirect(listener: TcpListener, https_port: u16, cancel: CancellationToken) {
+    let app = redirect_router(https_port);
+    let result = axum::serve(listener, app)
+        .with_graceful_shutdown(cancel.cancelled_owned())
+        .await;
+    if let Err(err) = result {
+        tracing::error!("HTTP redirect listener failed: {err}");
+    }
+}
+
+#[cfg(test)]
+mod test_tls {
+    use super::*;
+    use crate::config::ClientRoute;
+    use axum::body::Body;
+    use axum::http::header::LOCATION;
+    use axum::http::Version;
+    use axum::routing::get;
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, RootCertStore};
+    use std::net::SocketAddr;
+    use tokio::net::TcpStream;
+    use tokio_rustls::TlsConnector;
+
+    struct Files {
+        directory: PathBuf,
+        cert: PathBuf,
+        key: PathBuf,
+    }
+
+    impl Files {
+        fn new() -> Self {
+            let directory = std::env::temp_dir()
+                .join(format!("pirate_api_tls_{}", crate::token::random_token()));
+            std::fs::create_dir_all(&directory).unwrap();
+            Self {
+                cert: directory.join("cert.pem"),
+                key: directory.join("key.pem"),
+                directory,
+            }
+        }
+
+        /// Writes a new self-signed certificate and returns it.
+        fn write(&self) -> CertificateDer<'static> {
+            let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+            std::fs::write(&self.cert, cert.cert.pem()).unwrap();
+            std::fs::write(&self.key, cert.key_pair.serialize_pem()).unwrap();
+            cert.cert.der().clone()
+        }
+    }
+
+    impl Drop for Files {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.directory);
+        }
+    }
+
+    /// A CA in `files` and a client certificate for `billing` signed by it.
+    fn client_identity(files: &Files) -> (PathBuf, ClientIdentity) {
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "Pirate CA");
+        let ca = params.self_signed(&ca_key).unwrap();
+        let ca_file = files.directory.join("ca.pem");
+        std::fs::write(&ca_file, ca.pem()).unwrap();
+
+        let key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "billing");
+        params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ClientAuth];
+        let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+        let key = PrivateKeyDer::try_from(key.serialize_der()).unwrap();
+        (ca_file, (cert.der().clone(), key))
+    }
+
+    type ClientIdentity = (CertificateDer<'static>, PrivateKeyDer<'static>);
+
+    async fn start(
+        resolver: Arc<CertResolver>,
+        settings: &TlsSettings,
+    ) -> (SocketAddr, CancellationToken) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let config = Arc::new(resolver.server_config(settings).unwrap());
+        let app =
+            Router::new()
+                .route(
+                    "/",
+                    get(|ConnectInfo(peer): ConnectInfo<SocketAddr>| async move {
+                        peer.ip().to_string()
+                    }),
+                )
+                .route(
+                    "/client",
+                    get(|cert: ClientCert| async move { cert.subject }),
+                );
+        let stop = CancellationToken::new();
+        tokio::spawn(serve(listener, config, app, stop.clone().cancelled_owned()));
+        (addr, stop)
+    }
+
+    /// Connects trusting only `trusted` and returns the negotiated protocol
+    /// and the certificate the server presented.
+    async fn connect(
+        addr: SocketAddr,
+        trusted: &CertificateDer<'static>,
+        alpn: &[u8],
+        identity: Option<ClientIdentity>,
+    ) -> (
+        tokio_rustls::client::TlsStream<TcpStream>,
+        CertificateDer<'static>,
+    ) {
+        let mut roots = RootCertStore::empty();
+        roots.add(trusted.clone()).unwrap();
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let mut config = match identity {
+            Some((cert, key)) => builder.with_client_auth_cert(vec![cert], key).unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+        config.alpn_protocols = vec![alpn.to_vec()];
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let stream = TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), tcp)
+            .await
+            .unwrap();
+        let presented = stream.get_ref().1.peer_certificates().unwrap()[0].clone();
+        (stream, presented)
+    }
+
+    fn request() -> axum::http::Request<String> {
+        get_request("/")
+    }
+
+    fn get_request(path: &str) -> axum::http::Request<String> {
+        axum::http::Request::builder()
+            .uri(format!("https://localhost{path}"))
+            .header(HOST, "localhost")
+            .body(String::new())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_http2_and_http1() {
+        let files = Files::new();
+        let cert = files.write();
+        let resolver = Arc::new(CertResolver::new(files.cert.clone(), files.key.clone()).unwrap());
+        let (addr, stop) = start(resolver, &TlsSettings::default()).await;
+
+        let (stream, _) = connect(addr, &cert, b"h2", None).await;
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+        let (mut sender, connection) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+                .await
+                .unwrap();
+        tokio::spawn(connection);
+        let response = sender.send_request(request()).await.unwrap();
+        assert_eq!(response.version(), Version::HTTP_2);
+        let body = axum::body::to_bytes(Body::new(response.into_body()), 1024)
+            .await
+            .unwrap();
+        assert_eq!(body, "127.0.0.1");
+
+        let (stream, _) = connect(addr, &cert, b"http/1.1", None).await;
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+        let response = sender.send_request(request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), Version::HTTP_11);
+        stop.cancel();
+    }
+
+    #[tokio::test]
+    async fn test_reload() {
+        let files = Files::new();
+        let first = files.write();
+        let resolver = Arc::new(CertResolver::new(files.cert.clone(), files.key.clone()).unwrap());
+        let (addr, stop) = start(resolver.clone(), &TlsSettings::default()).await;
+        assert!(!resolver.reload().unwrap());
+
+        let second = files.write();
+        assert!(resolver.reload().unwrap());
+        let (_, presented) = connect(addr, &second, b"h2", None).await;
+        assert_eq!(presented, second);
+        assert_ne!(presented, first);
+
+        // a broken file keeps the last good certificate
+        std::fs::write(&files.key, "not a key").unwrap();
+        assert!(matches!(resolver.reload(), Err(TlsError::Pem { .. })));
+        let (_, presented) = connect(addr, &second, b"h2", None).await;
+        assert_eq!(presented, second);
+        stop.cancel();
+    }
+
+    /// Sends `GET path` over HTTP/1.1.
+    async fn get_over(
+        stream: tokio_rustls::client::TlsStream<TcpStream>,
+        path: &str,
+    ) -> hyper::Result<(StatusCode, String)> {
+        let (mut sender, connection) =
+            hyper::client::conn::http1::handshake(TokioIo::new(stream)).await?;
+        tokio::spawn(connection);
+        let response = sender.send_request(get_request(path)).await?;
+        let status = response.status();
+        let body = axum::body::to_bytes(Body::new(response.into_body()), 1024)
+            .await
+            .unwrap();
+        Ok((status, String::from_utf8(body.to_vec()).unwrap()))
+    }
+
+    #[tokio::test]
+    async fn test_client_cert() {
+        let files = Files::new();
+        let cert = files.write();
+        let (ca_file, identity) = client_identity(&files);
+        let resolver = Arc::new(CertResolver::new(files.cert.clone(), files.key.clone()).unwrap());
+        let settings = TlsSettings {
+            client_ca_file: Some(ca_file.clone()),
+            ..TlsSettings::default()
+        };
+        let (addr, stop) = start(resolver.clone(), &settings).await;
+
+        let (stream, _) = connect(
+            addr,
+            &cert,
+            b"http/1.1",
+            Some((identity.0.clone(), identity.1.clone_key())),
+        )
+        .await;
+        let response = get_over(stream, "/client").await.unwrap();
+        assert_eq!(response, (StatusCode::OK, "CN=billing".to_string()));
+
+        // the certificate is optional, but handlers can insist on it
+        let (stream, _) = connect(addr, &cert, b"http/1.1", None).await;
+        let (status, _) = get_over(stream, "/client").await.unwrap();
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        stop.cancel();
+
+        let required = TlsSettings {
+            require_client_cert: true,
+            ..settings
+        };
+        let (addr, stop) = start(resolver.clone(), &required).await;
+        let (stream, _) = connect(addr, &cert, b"http/1.1", Some(identity)).await;
+        assert_eq!(get_over(stream, "/").await.unwrap().0, StatusCode::OK);
+        let (stream, _) = connect(addr, &cert, b"http/1.1", None).await;
+        assert!(get_over(stream, "/").await.is_err());
+
+        // certificates from other CAs are rejected
+        let (_, other) = client_identity(&Files::new());
+        let (stream, _) = connect(addr, &cert, b"http/1.1", Some(other)).await;
+        assert!(get_over(stream, "/").await.is_err());
+        stop.cancel();
+
+        assert!(matches!(
+            resolver.server_config(&TlsSettings {
+                require_client_cert: true,
+                ..TlsSettings::default()
+            }),
+            Err(TlsError::MissingClientCa)
+        ));
+    }
+
+    #[test]
+    fn test_mismatched_key() {
+        let files = Files::new();
+        files.write();
+        let other = rcgen::KeyPair::generate().unwrap();
+        std::fs::write(&files.key, other.serialize_pem()).unwrap();
+        assert!(matches!(
+            CertResolver::new(files.cert.clone(), files.key.clone()),
+            Err(TlsError::Rustls(_))
+        ));
+        assert!(matches!(
+            CertResolver::from_settings(&TlsSettings {
+                cert_file: Some(files.cert.clone()),
+                ..TlsSettings::default()
+            }),
+            Err(TlsError::Incomplete)
+        ));
+    }
+
+    #[test]
+    fn test_client_routes_need_mtls() {
+        let files = Files::new();
+        files.write();
+        let client_routes = vec![ClientRoute {
+            method: Some("POST".to_string()),
+            route: "/v1/users".to_string(),
+            subjects: vec!["billing".to_string()],
+        }];
+        assert!(matches!(
+            CertResolver::from_settings(&TlsSettings {
+                client_routes: client_routes.clone(),
+                ..TlsSettings::default()
+            }),
+            Err(TlsError::ClientRoutesWithoutMtls)
+        ));
+        let tls = TlsSettings {
+            cert_file: Some(files.cert.clone()),
+            key_file: Some(files.key.clone()),
+            client_routes,
+            ..TlsSettings::default()
+        };
+        assert!(matches!(
+            CertResolver::from_settings(&tls),
+            Err(TlsError::ClientRoutesWithoutMtls)
+        ));
+        // the CA file itself is read by `server_config`
+        let mtls = TlsSettings {
+            client_ca_file: Some(files.cert.clone()),
+            ..tls
+        };
+        assert!(CertResolver::from_settings(&mtls).unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_redirect() {
+        let redirect = |https_port: u16, host: &str, uri: &str| {
+            let request = axum::http::Request::builder()
+                .uri(uri)
+                .header(HOST, host)
+                .body(Body::empty())
+                .unwrap();
+            async move { redirect_router(https_port).oneshot(request).await.unwrap() }
+        };
+        let response = redirect(3443, "example.com:8080", "/users?page=2").await;
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            response.headers()[LOCATION],
+            "https://example.com:3443/users?page=2"
+        );
+        let response = redirect(443, "[::1]:80", "/").await;
+        assert_eq!(response.headers()[LOCATION], "https://[::1]/");
+        let response = redirect(443, "", "/").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
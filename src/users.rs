@@ -0,0 +1,456 @@
+use axum::extract::rejection::{PathRejection, QueryRejection};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::IntoResponse;
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+use crate::access::Role;
+use crate::auth;
+use crate::error::{ApiError, ErrorBody};
+use crate::token::AuthUser;
+use crate::validation::{ValidJson, Validate, ValidationErrors, ValidationPolicy};
+use crate::{AppState, Email, Password, UserName};
+
+const DEFAULT_PER_PAGE: u32 = 20;
+const MAX_PER_PAGE: u32 = 100;
+
+/// A stored user as returned by the API.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, ToSchema)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    pub email: String,
+    pub email_verified: bool,
+    #[sqlx(try_from = "String")]
+    pub role: Role,
+    #[schema(example = "2024-11-25 12:00:00")]
+    pub created_at: String,
+}
+
+pub struct CreateUser {
+    pub username: UserName,
+    pub email: Email,
+    pub password: Password,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[schema(as = CreateUser)]
+pub struct CreateUserPayload {
+    #[schema(value_type = UserName)]
+    username: String,
+    #[schema(value_type = Email)]
+    email: String,
+    #[schema(value_type = Password)]
+    password: String,
+}
+
+impl Validate for CreateUser {
+    type Raw = CreateUserPayload;
+
+    fn validate(raw: Self::Raw, policy: &ValidationPolicy) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        let username = errors.check(
+            "username",
+            UserName::try_with_policy(raw.username, &policy.username),
+        );
+        let email = errors.check("email", Email::try_with_policy(raw.email, &policy.email));
+        let password = errors.check(
+            "password",
+            Password::try_new(raw.password, &policy.password),
+        );
+        match (username, email, password) {
+            (Some(username), Some(email), Some(password)) => Ok(Self {
+                username,
+                email,
+                password,
+            }),
+            _ => Err(errors),
+        }
+    }
+}
+
+/// Partial update; fields that are absent are left unchanged.
+pub struct UpdateUser {
+    pub username: Option<UserName>,
+    pub email: Option<Email>,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[schema(as = UpdateUser)]
+pub struct UpdateUserPayload {
+    #[schema(value_type = Option<UserName>)]
+    username: Option<String>,
+    #[schema(value_type = Option<Email>)]
+    email: Option<String>,
+}
+
+impl Validate for UpdateUser {
+    type Raw = UpdateUserPayload;
+
+    fn validate(raw: Self::Raw, policy: &ValidationPolicy) -> Result<Self, ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        let username = errors.check(
+            "username",
+            raw.username
+                .map(|username| UserName::try_with_policy(username, &policy.username))
+                .transpose(),
+        );
+        let email = errors.check(
+            "email",
+            raw.email
+                .map(|email| Email::try_with_policy(email, &policy.email))
+                .transpose(),
+        );
+        match (username, email) {
+            (Some(username), Some(email)) => Ok(Self { username, email }),
+            _ => Err(errors),
+        }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Pagination {
+    /// 1-based page number [default: 1]
+    page: Option<u32>,
+    /// Users per page, at most 100 [default: 20]
+    per_page: Option<u32>,
+}
+
+impl Pagination {
+    fn page(&self) -> u32 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    fn per_page(&self) -> u32 {
+        self.per_page
+            .unwrap_or(DEFAULT_PER_PAGE)
+            .clamp(1, MAX_PER_PAGE)
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UserPage {
+    items: Vec<User>,
+    page: u32,
+    per_page: u32,
+    total: i64,
+}
+
+/// Registers a user and mails a verification link to the given address.
+#[utoipa::path(
+    post,
+    path = "/v1/users",
+    tag = "users",
+    request_body = CreateUserPayload,
+    responses(
+        (status = CREATED, description = "User created", body = User,
+            headers(("Location" = String, description = "URL of the new user"))),
+        (status = UNPROCESSABLE_ENTITY, description = "Invalid fields", body = ErrorBody),
+        (status = CONFLICT, description = "Username or email already taken", body = ErrorBody),
+        (status = FORBIDDEN, description = "Client certificate missing or not allowed", body = ErrorBody),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded, see `Retry-After`", body = ErrorBody),
+    )
+)]
+pub async fn create_user(
+    State(state): State<AppState>,
+    ValidJson(user): ValidJson<CreateUser>,
+) -> Result<impl IntoResponse, ApiError> {
+    let password_hash = auth::hash_password(user.password).await?;
+    let user = state
+        .users
+        .create(&user.username, &user.email, &password_hash)
+        .await?;
+    state.metrics.users_created.inc();
+    state
+        .verifier
+        .send(&state.tokens, &*state.users, &user)
+        .await;
+    let location = format!("/v1/users/{}", user.id);
+    Ok((
+        StatusCode::CREATED,
+        [(header::LOCATION, location)],
+        Json(user),
+    ))
+}
+
+/// Registers a user; the original route, served like `POST /v1/users`.
+///
+/// Deprecated in favour of `POST /v1/users`, whose client certificate rules
+/// and rate limits it shares.
+#[utoipa::path(
+    post,
+    path = "/user/create",
+    tag = "users",
+    request_body = CreateUserPayload,
+    responses(
+        (status = CREATED, description = "User created", body = User,
+            headers(("Location" = String, description = "URL of the new user"))),
+        (status = UNPROCESSABLE_ENTITY, description = "Invalid fields", body = ErrorBody),
+        (status = CONFLICT, description = "Username or email already taken", body = ErrorBody),
+        (status = FORBIDDEN, description = "Client certificate missing or not allowed", body = ErrorBody),
+        (status = TOO_MANY_REQUESTS, description = "Rate limit exceeded, see `Retry-After`", body = ErrorBody),
+    )
+)]
+#[deprecated = "use `create_user`, served at `POST /v1/users`"]
+pub async fn create_user_legacy(
+    state: State<AppState>,
+    user: ValidJson<CreateUser>,
+) -> Result<impl IntoResponse, ApiError> {
+    create_user(state, user).await
+}
+
+#[utoipa::path(
+    get,
+    path = "/v1/users/{id}",
+    tag = "users",
+    security(("bearer" = [])),
+    params(("id" = i64, Path, description = "User id")),
+    responses(
+        (status = OK, body = User),
+        (status = UNAUTHORIZED, description = "Missing or invalid access token", body = ErrorBody),
+        (status = FORBIDDEN, description = "`users:read` is needed for other users", body = ErrorBody),
+        (status = NOT_FOUND, body = ErrorBody),
+    )
+)]
+pub async fn get_user(
+    State(state): State<AppState>,
+    id: Result<Path<i64>, PathRejection>,
+) -> Result<Json<User>, ApiError> {
+    let Path(id) = id?;
+    let user = state.users.get(id).await?;
+    Ok(Json(user))
+}
+
+/// Returns the user the access token was issued to.
+#[utoipa::path(
+    get,
+    path = "/v1/users/me",
+    tag = "users",
+    security(("bearer" = [])),
+    responses(
+        (status = OK, body = User),
+        (status = UNAUTHORIZED, description = "Missing or invalid access token", body = ErrorBody),
+    )
+)]
+pub async fn me(State(state): State<AppState>, user: AuthUser) -> Result<Json<User>, ApiError> {
+    Ok(Json(state.users.get(user.id).await?))
+}
+
+/// Lists all users.
+#[utoipa::path(
+    get,
+    path = "/v1/users",
+    tag = "users",
+    security(("bearer" = [])),
+    params(Pagination),
+    responses(
+        (status = OK, body = UserPage),
+        (status = UNAUTHORIZED, description = "Missing or invalid access token", body = ErrorBody),
+        (status = FORBIDDEN, description = "Missing `users:list`", body = ErrorBody),
+        (status = BAD_REQUEST, description = "Invalid query string", body = ErrorBody),
+    )
+)]
+pub async fn list_users(
+    State(state): State<AppState>,
+    pagination: Result<Query<Pagination>, QueryRejection>,
+) -> Result<Json<UserPage>, ApiError> {
+    let Query(pagination) = pagination?;
+    let (page, per_page) = (pagination.page(), pagination.per_page());
+    let offset = i64::from(page - 1) * i64::from(per_page);
+    let (items, total) = state.users.list(i64::from(per_page), offset).await?;
+    Ok(Json(UserPage {
+        items,
+        page,
+        per_page,
+        total,
+    }))
+}
+
+/// Changes username and/or email; a changed email has to be verified again.
+#[utoipa::path(
+    patch,
+    path = "/v1/users/{id}",
+    tag = "users",
+    security(("bearer" = [])),
+    params(("id" = i64, Path, description = "User id")),
+    request_body = UpdateUserPayload,
+    responses(
+        (status = OK, body = User),
+        (status = UNAUTHORIZED, description = "Missing or invalid access token", body = ErrorBody),
+        (status = FORBIDDEN, description = "`users:update` is needed for other users", body = ErrorBody),
+        (status = NOT_FOUND, body = ErrorBody),
+        (status = UNPROCESSABLE_ENTITY, description = "Invalid fields", body = ErrorBody),
+        (status = CONFLICT, description = "Username or email already taken", body = ErrorBody),
+    )
+)]
+pub async fn update_user(
+    State(state): State<AppState>,
+    id: Result<Path<i64>, PathRejection>,
+    ValidJson(update): ValidJson<UpdateUser>,
+) -> Result<Json<User>, ApiError> {
+    let Path(id) = id?;
+    let user = state.users.update(id, &update).await?;
+    if update.email.is_some() && !user.email_verified {
+        state
+            .verifier
+            .send(&state.tokens, &*state.users, &user)
+            .await;
+    }
+    Ok(Json(user))
+}
+
+/// Deletes a user together with their refresh tokens; the last admin can't
+/// be deleted, not even by themselves.
+#[utoipa::path(
+    delete,
+    path = "/v1/users/{id}",
+    tag = "users",
+    security(("bearer" = [])),
+    params(("id" = i64, Path, description = "User id")),
+    responses(
+        (status = NO_CONTENT, description = "User deleted"),
+        (status = UNAUTHORIZED, description = "Missing or invalid access token", body = ErrorBody),
+        (status = FORBIDDEN, description = "`users:delete` is needed for other users, or the user is the last admin", body = ErrorBody),
+        (status = NOT_FOUND, body = ErrorBody),
+    )
+)]
+pub async fn delete_user(
+    State(state): State<AppState>,
+    id: Result<Path<i64>, PathRejection>,
+) -> Result<StatusCode, ApiError> {
+    let Path(id) = id?;
+    state.users.delete_unless_last_admin(id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod test_create_user {
+    use super::*;
+    use crate::validation::FieldError;
+    use crate::UserNameError;
+
+    fn payload(username: &str, email: &str) -> CreateUserPayload {
+        CreateUserPayload {
+            username: username.to_string(),
+            email: email.to_string(),
+            password: "CorrectHorse42".to_string(),
+        }
+    }
+
+    fn validate(raw: CreateUserPayload) -> Result<CreateUser, ValidationErrors> {
+        CreateUser::validate(raw, &ValidationPolicy::default())
+    }
+
+    #[test]
+    fn test_good() {
+        assert!(validate(payload("HelloWorldIAmTim", "tim@example.com")).is_ok());
+    }
+
+    #[test]
+    fn test_collects_all_errors() {
+        let errors = validate(payload("test", "Abc.example.com")).err().unwrap();
+        let fields: Vec<_> = errors
+            .0
+            .iter()
+            .map(|e| (e.field.as_str(), e.code))
+            .collect();
+        assert_eq!(
+            fields,
+            [("username", "too_short"), ("email", "invalid_email")]
+        );
+    }
+
+    #[test]
+    fn test_password_policy() {
+        let raw = CreateUserPayload {
+            password: "short".to_string(),
+            ..payload("HelloWorldIAmTim", "tim@example.com")
+        };
+        let errors = validate(raw).err().unwrap();
+        assert_eq!(errors.0[0].field, "password");
+        assert_eq!(errors.0[0].code, "too_short");
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let errors = validate(payload("?testhallowkfahfla", "tim@example.com"))
+            .err()
+            .unwrap();
+        assert_eq!(
+            errors.0,
+            [FieldError::new(
+                "username",
+                "invalid_character",
+                UserNameError::InvalidCharacter("?".to_string())
+            )]
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_update_user {
+    use super::*;
+
+    #[test]
+    fn test_partial() {
+        let update = UpdateUser::validate(
+            UpdateUserPayload {
+                username: None,
+                email: Some("tim@example.com".to_string()),
+            },
+            &ValidationPolicy::default(),
+        )
+        .ok()
+        .unwrap();
+        assert!(update.username.is_none());
+        assert_eq!(update.email.unwrap().get(), "tim@example.com");
+    }
+
+    #[test]
+    fn test_revalidates() {
+        let errors = UpdateUser::validate(
+            UpdateUserPayload {
+                username: Some("test".to_string()),
+                email: Some("Abc.example.com".to_string()),
+            },
+            &ValidationPolicy::default(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(errors.0.len(), 2);
+    }
+
+    #[test]
+    fn test_uses_policy() {
+        let mut policy = ValidationPolicy::default();
+        policy.username.reserved_names = vec!["HelloWorldIAmAdmin".to_string()];
+        let errors = UpdateUser::validate(
+            UpdateUserPayload {
+                username: Some("HelloWorldIAmAdmin".to_string()),
+                email: None,
+            },
+            &policy,
+        )
+        .err()
+        .unwrap();
+        assert_eq!(errors.0[0].code, "reserved");
+    }
+}
+
+#[cfg(test)]
+mod test_list_users {
+    use super::*;
+
+    #[test]
+    fn test_pagination() {
+        let pagination = Pagination {
+            page: Some(0),
+            per_page: Some(1000),
+        };
+        assert_eq!(pagination.page(), 1);
+        assert_eq!(pagination.per_page(), MAX_PER_PAGE);
+    }
+}
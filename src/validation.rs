@@ -0,0 +1,97 @@
+use std::sync::Arc;
+
+use axum::extract::{FromRef, FromRequest, Request};
+use axum::Json;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use utoipa::ToSchema;
+
+use crate::error::ApiError;
+use crate::{EmailPolicy, PasswordPolicy, UserNamePolicy};
+
+/// A single failed field of a request body.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    #[schema(example = "too_short")]
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, code: &'static str, message: impl ToString) -> Self {
+        Self {
+            field: field.into(),
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+/// All field errors collected while validating a request body.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Error, Serialize)]
+#[error("{} invalid field(s)", .0.len())]
+#[serde(transparent)]
+pub struct ValidationErrors(pub Vec<FieldError>);
+
+impl ValidationErrors {
+    pub fn push(&mut self, error: FieldError) {
+        self.0.push(error);
+    }
+
+    /// Records the error of `result` under `field` and returns its value, if any.
+    pub fn check<T, E>(&mut self, field: &str, result: Result<T, E>) -> Option<T>
+    where
+        E: ErrorCode + ToString,
+    {
+        match result {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.push(FieldError::new(field, err.code(), err));
+                None
+            }
+        }
+    }
+}
+
+/// Machine-readable identifier of a validation error.
+pub trait ErrorCode {
+    fn code(&self) -> &'static str;
+}
+
+/// Deployment-specific rules applied while validating request bodies.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ValidationPolicy {
+    pub username: UserNamePolicy,
+    pub email: EmailPolicy,
+    pub password: PasswordPolicy,
+}
+
+/// A request body that is deserialized loosely and then validated field by field,
+/// so that every invalid field is reported instead of only the first.
+pub trait Validate: Sized {
+    type Raw: DeserializeOwned;
+
+    fn validate(raw: Self::Raw, policy: &ValidationPolicy) -> Result<Self, ValidationErrors>;
+}
+
+/// Like [`Json`], but validates the body through [`Validate`] and rejects with [`ApiError`].
+pub struct ValidJson<T>(pub T);
+
+#[axum::async_trait]
+impl<S, T> FromRequest<S> for ValidJson<T>
+where
+    S: Send + Sync,
+    Arc<ValidationPolicy>: FromRef<S>,
+    T: Validate,
+{
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let policy = Arc::<ValidationPolicy>::from_ref(state);
+        let Json(raw) = Json::<T::Raw>::from_request(req, state).await?;
+        Ok(Self(T::validate(raw, &policy)?))
+    }
+}
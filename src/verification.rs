@@ -0,0 +1,184 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::Json;
+use serde::Deserialize;
+use utoipa::IntoParams;
+
+use crate::error::{ApiError, ErrorBody};
+use crate::mail::{Mail, Mailer};
+use crate::repository::UserRepository;
+use crate::token::{hash_token, TokenKeys};
+use crate::users::User;
+use crate::AppState;
+
+/// Sends the one-time links users confirm their email address with.
+pub struct EmailVerifier {
+    pub mailer: Arc<dyn Mailer>,
+    /// Link target, the token is appended as `?token=`.
+    pub url: String,
+    pub ttl: Duration,
+}
+
+impl EmailVerifier {
+    /// Mails a verification link for the current email of `user`; links sent
+    /// earlier stop working.
+    ///
+    /// Delivery failures are logged and do not fail the calling request.
+    pub async fn send(&self, keys: &TokenKeys, users: &dyn UserRepository, user: &User) {
+        let token = match keys.issue_verification(user.id, &user.email, self.ttl) {
+            Ok(token) => token,
+            Err(err) => return tracing::error!("failed to issue verification token: {err}"),
+        };
+        if let Err(err) = users
+            .set_verification_token(user.id, &hash_token(&token))
+            .await
+        {
+            return tracing::error!("failed to store verification token: {err}");
+        }
+        let mail = Mail {
+            to: user.email.clone(),
+            subject: "Verify your email address".to_string(),
+            body: format!(
+                "Ahoy {},\n\nplease confirm your email address by opening\n\n{}?token={}\n\n\
+                 The link expires in {}.\n",
+                user.username,
+                self.url,
+                token,
+                format_ttl(self.ttl),
+            ),
+        };
+        if let Err(err) = self.mailer.send(mail).await {
+            tracing::error!(
+                "failed to send verification mail to user {}: {err}",
+                user.id
+            );
+        }
+    }
+}
+
+/// `3 hours` for whole hours, minutes otherwise, e.g. `90 minutes`.
+fn format_ttl(ttl: Duration) -> String {
+    let secs = ttl.as_secs();
+    let (count, unit) = if secs >= 3600 && secs.is_multiple_of(3600) {
+        (secs / 3600, "hour")
+    } else {
+        (secs.div_ceil(60).max(1), "minute")
+    };
+    if count == 1 {
+        format!("1 {unit}")
+    } else {
+        format!("{count} {unit}s")
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct VerifyQuery {
+    /// Token from the verification mail
+    token: String,
+}
+
+/// Marks the email address a verification link was sent to as verified.
+#[utoipa::path(
+    get,
+    path = "/v1/user/verify",
+    tag = "users",
+    params(VerifyQuery),
+    responses(
+        (status = OK, body = User),
+        (status = BAD_REQUEST, description = "Invalid, expired or already used token", body = ErrorBody),
+    )
+)]
+pub async fn verify_email(
+    State(state): State<AppState>,
+    query: Result<Query<VerifyQuery>, QueryRejection>,
+) -> Result<Json<User>, ApiError> {
+    let Query(query) = query?;
+    let (id, email) = state.tokens.verify_verification(&query.token)?;
+    // the signature alone would let a link be replayed until it expires
+    let stored = state
+        .users
+        .take_verification_token(&hash_token(&query.token))
+        .await?;
+    if stored != Some(id) {
+        return Err(ApiError::InvalidToken(
+            "Link already used or replaced by a newer one".to_string(),
+        ));
+    }
+    let user = state.users.mark_email_verified(id, &email).await?;
+    Ok(Json(user))
+}
+
+#[cfg(test)]
+mod test_verification {
+    use super::*;
+    use crate::access::Role;
+    use crate::mail::MemoryMailer;
+    use crate::repository::MemoryUserRepository;
+    use crate::token::{SigningKey, TokenSettings};
+
+    #[tokio::test]
+    async fn test_send() {
+        let keys = TokenKeys::new(&TokenSettings {
+            key: SigningKey::Hs256 {
+                secret: "secret".to_string(),
+            },
+            access_ttl: Duration::from_secs(60),
+            refresh_ttl: Duration::from_secs(60),
+        })
+        .unwrap();
+        let mailer = Arc::new(MemoryMailer::default());
+        let verifier = EmailVerifier {
+            mailer: mailer.clone(),
+            url: "http://localhost:3000/v1/user/verify".to_string(),
+            ttl: Duration::from_secs(24 * 3600),
+        };
+        let user = User {
+            id: 3,
+            username: "HelloWorldIAmTim".to_string(),
+            email: "tim@example.com".to_string(),
+            email_verified: false,
+            role: Role::User,
+            created_at: String::new(),
+        };
+        let users = MemoryUserRepository::default();
+        verifier.send(&keys, &users, &user).await;
+
+        let mail = {
+            let sent = mailer.sent.lock().unwrap();
+            assert_eq!(sent.len(), 1);
+            sent[0].clone()
+        };
+        assert_eq!(mail.to, "tim@example.com");
+        assert!(mail.body.contains("expires in 24 hours"));
+        let token = mail
+            .body
+            .split("?token=")
+            .nth(1)
+            .and_then(|rest| rest.split_whitespace().next())
+            .unwrap();
+        assert_eq!(
+            keys.verify_verification(token).unwrap(),
+            (3, "tim@example.com".to_string())
+        );
+        assert_eq!(
+            users
+                .take_verification_token(&hash_token(token))
+                .await
+                .unwrap(),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn test_format_ttl() {
+        assert_eq!(format_ttl(Duration::from_secs(24 * 3600)), "24 hours");
+        assert_eq!(format_ttl(Duration::from_secs(3600)), "1 hour");
+        assert_eq!(format_ttl(Duration::from_secs(90 * 60)), "90 minutes");
+        assert_eq!(format_ttl(Duration::from_secs(15 * 60)), "15 minutes");
+        assert_eq!(format_ttl(Duration::from_secs(30)), "1 minute");
+    }
+}
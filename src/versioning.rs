@@ -0,0 +1,210 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::extract::{ConnectInfo, MatchedPath, Request, State};
+use axum::http::header::{ACCEPT, LINK, USER_AGENT, VARY};
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode, Uri};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::config::{route_matches, DeprecatedRoute};
+use crate::error::ApiError;
+
+const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+const SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+/// API versions served under `/v{n}`, oldest first.
+pub const VERSIONS: &[u32] = &[1];
+
+/// Media type that asks for a version in `Accept`, followed by `{n}+json`.
+const VENDOR_MEDIA_TYPE: &str = "application/vnd.pirate.v";
+
+/// A deprecated route served by the same handler as its successor.
+#[derive(Debug, Clone, Copy)]
+pub struct Alias {
+    pub method: &'static str,
+    pub route: &'static str,
+    pub successor: &'static str,
+}
+
+/// The aliases registered by [`crate::build_router`]; the default
+/// `api.deprecated_routes` and the route rules of client certificates and
+/// rate limits are derived from them.
+pub const ALIASES: &[Alias] = &[Alias {
+    method: "POST",
+    route: "/user/create",
+    successor: "/v1/users",
+}];
+
+/// Paths of the versioned API, without their `/v{n}` prefix.
+const VERSIONED_PATHS: &[&str] = &["/users", "/auth", "/user/verify"];
+
+fn latest() -> u32 {
+    VERSIONS[VERSIONS.len() - 1]
+}
+
+fn parse_version(number: &str) -> Option<u32> {
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    number.parse().ok()
+}
+
+/// The version of a `/v{n}/...` path.
+fn path_version(path: &str) -> Option<u32> {
+    let rest = path.strip_prefix("/v")?;
+    parse_version(rest.split_once('/').map_or(rest, |(number, _)| number))
+}
+
+/// The version asked for with `application/vnd.pirate.v{n}+json`.
+fn accept_version(headers: &HeaderMap) -> Option<u32> {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|range| {
+            let media_type = range.split(';').next()?.trim().to_ascii_lowercase();
+            parse_version(
+                media_type
+                    .strip_prefix(VENDOR_MEDIA_TYPE)?
+                    .strip_suffix("+json")?,
+            )
+        })
+}
+
+fn is_versioned(path: &str) -> bool {
+    VERSIONED_PATHS.iter().any(|prefix| {
+        path.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// Serves unversioned paths of the API with the version asked for in `Accept`,
+/// or the latest one, by prefixing them with `/v{n}`.
+///
+/// Has to wrap the router, routing is done by the time route layers run.
+pub async fn negotiate(mut request: Request, next: Next) -> Response {
+    let uri = request.uri();
+    if !is_versioned(uri.path()) {
+        return next.run(request).await;
+    }
+    let version = accept_version(request.headers()).unwrap_or_else(latest);
+    let path = match uri.query() {
+        Some(query) => format!("/v{version}{}?{query}", uri.path()),
+        None => format!("/v{version}{}", uri.path()),
+    };
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = path.parse().ok();
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *request.uri_mut() = uri;
+    }
+    let mut response = next.run(request).await;
+    response
+        .headers_mut()
+        .append(VARY, HeaderValue::from_static("accept"));
+    response
+}
+
+/// 406 for paths of versions that do not exist, 404 for everything else.
+pub async fn fallback(uri: Uri) -> Response {
+    match path_version(uri.path()) {
+        Some(version) if !VERSIONS.contains(&version) => {
+            ApiError::UnsupportedVersion(version).into_response()
+        }
+        _ => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Adds `Deprecation`, `Sunset` and `Link` headers to the responses of the
+/// routes in `api.deprecated_routes` and logs who still calls them.
+pub async fn deprecation(
+    State(routes): State<Arc<Vec<DeprecatedRoute>>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(path) = request.extensions().get::<MatchedPath>() else {
+        return next.run(request).await;
+    };
+    let method = request.method().as_str();
+    let Some(route) = routes
+        .iter()
+        .find(|route| route_matches(route.method.as_deref(), &route.route, method, path.as_str()))
+    else {
+        return next.run(request).await;
+    };
+    let client = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip().to_string());
+    let user_agent = request
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok());
+    tracing::info!(
+        route = route.route,
+        client,
+        user_agent,
+        "deprecated route called"
+    );
+
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    if let Ok(date) = HeaderValue::try_from(format!("@{}", route.deprecated_at.unix())) {
+        headers.insert(DEPRECATION, date);
+    }
+    if let Some(sunset) = route.sunset_at {
+        if let Ok(sunset) = HeaderValue::from_str(&sunset.to_string()) {
+            headers.insert(SUNSET, sunset);
+        }
+    }
+    if let Some(successor) = &route.successor {
+        if let Ok(link) =
+            HeaderValue::from_str(&format!("<{successor}>; rel=\"successor-version\""))
+        {
+            headers.append(LINK, link);
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod test_versioning {
+    use super::*;
+
+    #[test]
+    fn test_path_version() {
+        assert_eq!(path_version("/v1/users"), Some(1));
+        assert_eq!(path_version("/v12"), Some(12));
+        assert_eq!(path_version("/v+1/users"), None);
+        assert_eq!(path_version("/version"), None);
+        assert_eq!(path_version("/users"), None);
+    }
+
+    #[test]
+    fn test_accept_version() {
+        let accept = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT, value.parse().unwrap());
+            accept_version(&headers)
+        };
+        assert_eq!(accept("application/vnd.pirate.v1+json"), Some(1));
+        assert_eq!(
+            accept("text/html, Application/Vnd.Pirate.V2+JSON; q=0.9"),
+            Some(2)
+        );
+        assert_eq!(accept("application/json"), None);
+        assert_eq!(accept("application/vnd.pirate.v1"), None);
+        assert_eq!(accept_version(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_is_versioned() {
+        assert!(is_versioned("/users"));
+        assert!(is_versioned("/users/1"));
+        assert!(is_versioned("/user/verify"));
+        assert!(!is_versioned("/user/create"));
+        assert!(!is_versioned("/usersettings"));
+        assert!(!is_versioned("/healthz"));
+    }
+}
@@ -0,0 +1,333 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{TestApp, TestResponse};
+use pirate_api::access::{Access, Permission, Role};
+use pirate_api::repository::UserRepository;
+use serde_json::json;
+
+async fn make(app: &TestApp, id: i64, role: Role) {
+    let access = Access {
+        role,
+        ..Access::default()
+    };
+    app.users.set_access(id, &access).await.unwrap();
+}
+
+async fn set_role(app: &TestApp, token: &str, id: i64, role: &str) -> TestResponse {
+    app.send(
+        Method::PUT,
+        &format!("/v1/users/{id}/access"),
+        Some(token),
+        Some(&json!({ "role": role })),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn test_anonymous() {
+    let app = TestApp::new();
+    let (id, _) = app.sign_up("HelloWorldIAmTim").await;
+    for (method, uri) in [
+        (Method::GET, "/v1/users".to_string()),
+        (Method::GET, format!("/v1/users/{id}")),
+        (Method::DELETE, format!("/v1/users/{id}")),
+        (Method::PUT, format!("/v1/users/{id}/access")),
+    ] {
+        let response = app.send(method, &uri, None, None).await;
+        assert_eq!(response.status, StatusCode::UNAUTHORIZED, "{uri}");
+        assert_eq!(response.body["code"], "unauthorized");
+    }
+}
+
+#[tokio::test]
+async fn test_user_is_denied() {
+    let app = TestApp::new();
+    let (tim, token) = app.sign_up("HelloWorldIAmTim").await;
+    let (tom, _) = app.sign_up("HelloWorldIAmTom").await;
+
+    let response = app.send(Method::GET, "/v1/users", Some(&token), None).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert_eq!(response.body["code"], "permission_denied");
+    assert_eq!(response.body["required_permission"], "users:list");
+
+    let other = format!("/v1/users/{tom}");
+    let response = app.send(Method::DELETE, &other, Some(&token), None).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert_eq!(response.body["required_permission"], "users:delete");
+    let response = app.send(Method::GET, &other, Some(&token), None).await;
+    assert_eq!(response.body["required_permission"], "users:read");
+
+    // nobody grants themselves permissions
+    let response = app
+        .send(
+            Method::PUT,
+            &format!("/v1/users/{tim}/access"),
+            Some(&token),
+            Some(&json!({ "role": "admin" })),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert_eq!(response.body["required_permission"], "roles:manage");
+}
+
+#[tokio::test]
+async fn test_owner() {
+    let app = TestApp::new();
+    let (id, token) = app.sign_up("HelloWorldIAmTim").await;
+    let own = format!("/v1/users/{id}");
+
+    let response = app.send(Method::GET, &own, Some(&token), None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["role"], "user");
+    let response = app
+        .send(Method::GET, &format!("{own}/access"), Some(&token), None)
+        .await;
+    assert_eq!(response.body, json!({ "role": "user", "permissions": [] }));
+    let response = app
+        .send(
+            Method::PATCH,
+            &own,
+            Some(&token),
+            Some(&json!({ "username": "HelloWorldIAmTom" })),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let response = app.send(Method::DELETE, &own, Some(&token), None).await;
+    assert_eq!(response.status, StatusCode::NO_CONTENT);
+
+    // the token outlives the user
+    let response = app.send(Method::GET, "/v1/users", Some(&token), None).await;
+    assert_eq!(response.status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_moderator() {
+    let app = TestApp::new();
+    let (moderator, token) = app.sign_up("HelloWorldIAmTim").await;
+    let (tom, _) = app.sign_up("HelloWorldIAmTom").await;
+    make(&app, moderator, Role::Moderator).await;
+
+    // user enumeration is left to admins
+    let response = app.send(Method::GET, "/v1/users", Some(&token), None).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert_eq!(response.body["required_permission"], "users:list");
+    let other = format!("/v1/users/{tom}");
+    let response = app.send(Method::GET, &other, Some(&token), None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let response = app
+        .send(
+            Method::PATCH,
+            &other,
+            Some(&token),
+            Some(&json!({ "username": "HelloWorldIAmAnn" })),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let response = app.send(Method::DELETE, &other, Some(&token), None).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_admin() {
+    let app = TestApp::new();
+    let (admin, token) = app.sign_up("HelloWorldIAmTim").await;
+    let (tom, tom_token) = app.sign_up("HelloWorldIAmTom").await;
+    let (ann, _) = app.sign_up("HelloWorldIAmAnn").await;
+    make(&app, admin, Role::Admin).await;
+    make(&app, ann, Role::Moderator).await;
+
+    let response = app.send(Method::GET, "/v1/users", Some(&token), None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["items"][0]["role"], "admin");
+
+    let response = app
+        .send(
+            Method::DELETE,
+            &format!("/v1/users/{ann}"),
+            Some(&token),
+            None,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::NO_CONTENT);
+
+    // a single permission on top of the user role
+    let response = app
+        .send(
+            Method::PUT,
+            &format!("/v1/users/{tom}/access"),
+            Some(&token),
+            Some(&json!({ "role": "user", "permissions": ["users:list"] })),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let response = app
+        .send(Method::GET, "/v1/users", Some(&tom_token), None)
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    let response = app
+        .send(
+            Method::GET,
+            &format!("/v1/users/{admin}"),
+            Some(&tom_token),
+            None,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+    let response = app
+        .send(
+            Method::PUT,
+            &format!("/v1/users/{tom}/access"),
+            Some(&token),
+            Some(&json!({ "role": "captain" })),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let response = app
+        .send(
+            Method::PUT,
+            &format!("/v1/users/{}/access", ann),
+            Some(&token),
+            Some(&json!({ "role": "user" })),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_higher_role_is_off_limits() {
+    let app = TestApp::new();
+    let (admin, _) = app.sign_up("HelloWorldIAmTim").await;
+    let (moderator, moderator_token) = app.sign_up("HelloWorldIAmTom").await;
+    let (ann, ann_token) = app.sign_up("HelloWorldIAmAnn").await;
+    make(&app, admin, Role::Admin).await;
+    make(&app, moderator, Role::Moderator).await;
+    let access = Access {
+        role: Role::User,
+        permissions: [Permission::DeleteUsers, Permission::ManageRoles].into(),
+    };
+    app.users.set_access(ann, &access).await.unwrap();
+
+    let response = app
+        .send(
+            Method::PATCH,
+            &format!("/v1/users/{admin}"),
+            Some(&moderator_token),
+            Some(&json!({ "username": "HelloWorldIAmBob" })),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert_eq!(response.body["code"], "forbidden");
+    assert_eq!(
+        app.users.get(admin).await.unwrap().username,
+        "HelloWorldIAmTim"
+    );
+
+    // the permission alone is not enough
+    for id in [admin, moderator] {
+        let response = app
+            .send(
+                Method::DELETE,
+                &format!("/v1/users/{id}"),
+                Some(&ann_token),
+                None,
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+    }
+    for (id, role) in [(moderator, "user"), (ann, "admin")] {
+        let response = set_role(&app, &ann_token, id, role).await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN, "{role}");
+    }
+    assert_eq!(
+        app.users.access(moderator).await.unwrap().role,
+        Role::Moderator
+    );
+    assert_eq!(app.users.access(ann).await.unwrap(), access);
+}
+
+#[tokio::test]
+async fn test_no_permissions_beyond_own() {
+    let app = TestApp::new();
+    let (manager, token) = app.sign_up("HelloWorldIAmTim").await;
+    let (tom, _) = app.sign_up("HelloWorldIAmTom").await;
+    let access = Access {
+        role: Role::User,
+        permissions: [Permission::ManageRoles, Permission::ReadUsers].into(),
+    };
+    app.users.set_access(manager, &access).await.unwrap();
+
+    for id in [manager, tom] {
+        let response = app
+            .send(
+                Method::PUT,
+                &format!("/v1/users/{id}/access"),
+                Some(&token),
+                Some(&json!({ "role": "user", "permissions": ["users:read", "users:delete"] })),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        assert_eq!(response.body["code"], "permission_denied");
+        assert_eq!(response.body["required_permission"], "users:delete");
+    }
+    assert_eq!(app.users.access(manager).await.unwrap(), access);
+    assert_eq!(app.users.access(tom).await.unwrap(), Access::default());
+
+    // what the caller holds can be passed on
+    let response = app
+        .send(
+            Method::PUT,
+            &format!("/v1/users/{tom}/access"),
+            Some(&token),
+            Some(&json!({ "role": "user", "permissions": ["users:read"] })),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_last_admin() {
+    let app = TestApp::new();
+    let (admin, token) = app.sign_up("HelloWorldIAmTim").await;
+    let (tom, _) = app.sign_up("HelloWorldIAmTom").await;
+    make(&app, admin, Role::Admin).await;
+
+    let response = set_role(&app, &token, admin, "moderator").await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert_eq!(response.body["code"], "last_admin");
+    assert_eq!(app.users.access(admin).await.unwrap().role, Role::Admin);
+
+    make(&app, tom, Role::Admin).await;
+    let response = set_role(&app, &token, admin, "moderator").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(app.users.count_admins().await.unwrap(), 1);
+}
+
+#[tokio::test]
+async fn test_last_admin_delete() {
+    let app = TestApp::new();
+    let (admin, token) = app.sign_up("HelloWorldIAmTim").await;
+    let (tom, tom_token) = app.sign_up("HelloWorldIAmTom").await;
+    make(&app, admin, Role::Admin).await;
+    let own = format!("/v1/users/{admin}");
+
+    // not even the admin themselves
+    let response = app.send(Method::DELETE, &own, Some(&token), None).await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+    assert_eq!(response.body["code"], "last_admin");
+    assert_eq!(app.users.count_admins().await.unwrap(), 1);
+
+    make(&app, tom, Role::Admin).await;
+    let response = app.send(Method::DELETE, &own, Some(&token), None).await;
+    assert_eq!(response.status, StatusCode::NO_CONTENT);
+    let response = app
+        .send(
+            Method::DELETE,
+            &format!("/v1/users/{tom}"),
+            Some(&tom_token),
+            None,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::FORBIDDEN);
+}
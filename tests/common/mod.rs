@@ -0,0 +1,189 @@
+//! Runs the whole router in-process against an in-memory store.
+
+// not every test binary uses every helper
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::{to_bytes, Body};
+use axum::extract::connect_info::MockConnectInfo;
+use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+use axum::http::{HeaderMap, Method, Request, StatusCode};
+use axum::Router;
+use pirate_api::config::{ApiSettings, HttpSettings, RateLimitSettings};
+use pirate_api::layers::HttpLayers;
+use pirate_api::mail::MemoryMailer;
+use pirate_api::metrics::Metrics;
+use pirate_api::openapi::DocsScript;
+use pirate_api::rate_limit::RateLimiter;
+use pirate_api::repository::MemoryUserRepository;
+use pirate_api::shutdown::Lifecycle;
+use pirate_api::token::{SigningKey, TokenKeys, TokenSettings};
+use pirate_api::validation::ValidationPolicy;
+use pirate_api::verification::EmailVerifier;
+use pirate_api::{build_router, AppState};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+pub struct TestApp {
+    router: Router,
+    pub users: Arc<MemoryUserRepository>,
+    pub mailer: Arc<MemoryMailer>,
+}
+
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    /// The body parsed as JSON, `Value::Null` if it is not.
+    pub body: Value,
+}
+
+impl TestApp {
+    pub fn new() -> Self {
+        Self::with_policy(ValidationPolicy::default())
+    }
+
+    /// An app with rate limiting disabled, so tests can send as many requests as they like.
+    pub fn with_policy(policy: ValidationPolicy) -> Self {
+        Self::build(
+            policy,
+            RateLimitSettings {
+                enabled: false,
+                ..RateLimitSettings::default()
+            },
+        )
+    }
+
+    pub fn with_rate_limit(rate_limit: RateLimitSettings) -> Self {
+        Self::build(ValidationPolicy::default(), rate_limit)
+    }
+
+    fn build(policy: ValidationPolicy, rate_limit: RateLimitSettings) -> Self {
+        let users = Arc::new(MemoryUserRepository::default());
+        let mailer = Arc::new(MemoryMailer::default());
+        let tokens = TokenKeys::new(&TokenSettings {
+            key: SigningKey::Hs256 {
+                secret: "integration-test-secret".to_string(),
+            },
+            access_ttl: Duration::from_secs(900),
+            refresh_ttl: Duration::from_secs(3600),
+        })
+        .unwrap();
+        let state = AppState {
+            users: users.clone(),
+            policy: Arc::new(policy),
+            tokens: Arc::new(tokens),
+            verifier: Arc::new(EmailVerifier {
+                mailer: mailer.clone(),
+                url: "http://localhost:3000/v1/user/verify".to_string(),
+                ttl: Duration::from_secs(3600),
+            }),
+            lifecycle: Lifecycle::default(),
+            metrics: Arc::new(Metrics::default()),
+            rate_limiter: Arc::new(RateLimiter::new(rate_limit)),
+            client_routes: Arc::new(Vec::new()),
+            deprecated_routes: Arc::new(ApiSettings::default().deprecated_routes),
+            docs: DocsScript::Cdn,
+        };
+        let client: SocketAddr = ([127, 0, 0, 1], 40000).into();
+        Self {
+            router: build_router(state, &HttpLayers::new(&HttpSettings::default()).unwrap())
+                .layer(MockConnectInfo(client)),
+            users,
+            mailer,
+        }
+    }
+
+    pub async fn request(&self, request: Request<Body>) -> TestResponse {
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        TestResponse {
+            status,
+            headers,
+            body: serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+        }
+    }
+
+    /// Sends `body` as is with the given content type.
+    pub async fn post(&self, uri: &str, content_type: &str, body: &str) -> TestResponse {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(uri)
+            .header(CONTENT_TYPE, content_type)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        self.request(request).await
+    }
+
+    pub async fn post_json(&self, uri: &str, body: &Value) -> TestResponse {
+        self.post(uri, "application/json", &body.to_string()).await
+    }
+
+    /// Sends `body` as JSON, authenticated with `token` if given.
+    pub async fn send(
+        &self,
+        method: Method,
+        uri: &str,
+        token: Option<&str>,
+        body: Option<&Value>,
+    ) -> TestResponse {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+        }
+        let request = match body {
+            Some(body) => request
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        };
+        self.request(request.unwrap()).await
+    }
+
+    /// Registers `username` and logs in, returning the user id and access token.
+    pub async fn sign_up(&self, username: &str) -> (i64, String) {
+        let password = "Correct horse battery staple 1!";
+        let response = self
+            .post_json(
+                "/v1/users",
+                &json!({
+                    "username": username,
+                    "email": format!("{}@example.com", username.to_lowercase()),
+                    "password": password,
+                }),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::CREATED, "{:?}", response.body);
+        let id = response.body["id"].as_i64().unwrap();
+        let response = self
+            .post_json(
+                "/v1/auth/login",
+                &json!({ "username": username, "password": password }),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK, "{:?}", response.body);
+        let token = response.body["access_token"].as_str().unwrap().to_string();
+        (id, token)
+    }
+}
+
+impl TestResponse {
+    /// The `(field, code)` pairs of a validation error body.
+    pub fn field_errors(&self) -> Vec<(String, String)> {
+        self.body["errors"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|error| {
+                (
+                    error["field"].as_str().unwrap().to_string(),
+                    error["code"].as_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    }
+}
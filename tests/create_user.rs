@@ -0,0 +1,209 @@
+mod common;
+
+use axum::http::header::{LINK, LOCATION};
+use axum::http::StatusCode;
+use common::TestApp;
+use pirate_api::repository::UserRepository;
+use pirate_api::validation::ValidationPolicy;
+use pirate_api::{EmailPolicy, UserNamePattern, UserNamePolicy};
+use serde_json::{json, Value};
+
+const PASSWORD: &str = "Correct horse battery staple 1!";
+
+fn payload(username: &str, email: &str) -> Value {
+    json!({ "username": username, "email": email, "password": PASSWORD })
+}
+
+/// Usernames have to start with an uppercase letter and `PirateCaptain1` is taken.
+fn strict_app() -> TestApp {
+    TestApp::with_policy(ValidationPolicy {
+        username: UserNamePolicy {
+            pattern: Some(UserNamePattern::try_from("^[A-Z]".to_string()).unwrap()),
+            reserved_names: vec!["PirateCaptain1".to_string()],
+            ..UserNamePolicy::default()
+        },
+        email: EmailPolicy {
+            denied_domains: vec!["spam.example.com".to_string()],
+            ..EmailPolicy::default()
+        },
+        ..ValidationPolicy::default()
+    })
+}
+
+#[tokio::test]
+async fn test_success() {
+    let app = TestApp::new();
+    let response = app
+        .post_json("/users", &payload("HelloWorldIAmTim", "tim@example.com"))
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    let id = response.body["id"].as_i64().unwrap();
+    assert_eq!(response.headers[LOCATION], format!("/v1/users/{id}"));
+    assert_eq!(response.body["username"], "HelloWorldIAmTim");
+    assert_eq!(response.body["email"], "tim@example.com");
+    assert_eq!(response.body["email_verified"], false);
+    assert!(response.body.get("password").is_none());
+
+    let stored = app.users.get(id).await.unwrap();
+    assert_eq!(stored.username, "HelloWorldIAmTim");
+    let sent = app.mailer.sent.lock().unwrap();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].to, "tim@example.com");
+}
+
+#[tokio::test]
+async fn test_legacy_route() {
+    let app = TestApp::new();
+    let response = app
+        .post_json(
+            "/user/create",
+            &payload("HelloWorldIAmTim", "tim@example.com"),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    assert_eq!(response.headers["deprecation"], "@1790812800");
+    assert_eq!(response.headers["sunset"], "Thu, 01 Jul 2027 00:00:00 GMT");
+    assert_eq!(
+        response.headers[LINK],
+        r#"</v1/users>; rel="successor-version""#
+    );
+
+    let response = app
+        .post_json("/v1/users", &payload("HelloWorldIAmTom", "tom@example.com"))
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    assert!(!response.headers.contains_key("deprecation"));
+}
+
+#[tokio::test]
+async fn test_duplicate() {
+    let app = TestApp::new();
+    app.post_json("/users", &payload("HelloWorldIAmTim", "tim@example.com"))
+        .await;
+    let response = app
+        .post_json("/users", &payload("HeIIoWor1dIAmTim", "tom@example.com"))
+        .await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+    assert_eq!(response.body["code"], "conflict");
+}
+
+#[tokio::test]
+async fn test_username_errors() {
+    let app = strict_app();
+    for (username, code) in [
+        ("Tim", "too_short"),
+        ("HelloWorldIAmTimAndMyNameIsFarTooLong", "too_long"),
+        ("HelloWorld!IAmTim", "invalid_character"),
+        ("helloWorldIAmTim", "pattern_mismatch"),
+        ("PirateCaptain1", "reserved"),
+    ] {
+        let response = app
+            .post_json("/users", &payload(username, "tim@example.com"))
+            .await;
+        assert_eq!(
+            response.status,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "{username}"
+        );
+        assert_eq!(response.body["code"], "validation_failed");
+        assert_eq!(
+            response.field_errors(),
+            [("username".to_string(), code.to_string())],
+            "{username}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_bad_emails() {
+    let app = strict_app();
+    for (email, code) in [
+        ("not-an-email", "invalid_email"),
+        ("A@b@c@example.com", "invalid_email"),
+        ("tim@example.com ", "invalid_email"),
+        ("tim@eu.spam.example.com", "domain_denied"),
+    ] {
+        let response = app
+            .post_json("/users", &payload("HelloWorldIAmTim", email))
+            .await;
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY, "{email}");
+        assert_eq!(
+            response.field_errors(),
+            [("email".to_string(), code.to_string())],
+            "{email}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_all_fields_reported() {
+    let app = TestApp::new();
+    let response = app
+        .post_json(
+            "/users",
+            &json!({ "username": "Tim", "email": "nope", "password": "short" }),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let fields: Vec<String> = response
+        .field_errors()
+        .into_iter()
+        .map(|(field, _)| field)
+        .collect();
+    assert!(fields.contains(&"username".to_string()));
+    assert!(fields.contains(&"email".to_string()));
+    assert!(fields.contains(&"password".to_string()));
+    assert!(app.mailer.sent.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_malformed_json() {
+    let app = TestApp::new();
+    let response = app
+        .post(
+            "/users",
+            "application/json",
+            r#"{"username": "HelloWorldIAmTim","#,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.body["code"], "invalid_json");
+    assert!(response.body["request_id"].is_string());
+
+    // well-formed, but a field is missing
+    let response = app
+        .post_json("/users", &json!({ "username": "HelloWorldIAmTim" }))
+        .await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(response.body["code"], "invalid_json");
+}
+
+#[tokio::test]
+async fn test_wrong_content_type() {
+    let app = TestApp::new();
+    let body = payload("HelloWorldIAmTim", "tim@example.com").to_string();
+    for content_type in ["text/plain", "application/x-www-form-urlencoded"] {
+        let response = app.post("/users", content_type, &body).await;
+        assert_eq!(
+            response.status,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "{content_type}"
+        );
+        assert_eq!(response.body["code"], "invalid_json");
+    }
+    let (users, total) = app.users.list(10, 0).await.unwrap();
+    assert!(users.is_empty());
+    assert_eq!(total, 0);
+}
+
+#[tokio::test]
+async fn test_body_too_large() {
+    let app = TestApp::new();
+    let username = "a".repeat(100 * 1024);
+    let response = app
+        .post_json("/users", &payload(&username, "tim@example.com"))
+        .await;
+    assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(response.body["code"], "payload_too_large");
+    assert_eq!(response.headers["x-content-type-options"], "nosniff");
+}
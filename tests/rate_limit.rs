@@ -0,0 +1,97 @@
+mod common;
+
+use axum::http::header::RETRY_AFTER;
+use axum::http::StatusCode;
+use common::{TestApp, TestResponse};
+use pirate_api::config::{RateLimitKey, RateLimitRule, RateLimitSettings};
+use pirate_api::repository::UserRepository;
+use serde_json::{json, Value};
+
+fn header<'a>(response: &'a TestResponse, name: &str) -> &'a str {
+    response.headers[name].to_str().unwrap()
+}
+
+fn seconds(response: &TestResponse, name: &str) -> u64 {
+    header(response, name).parse().unwrap()
+}
+
+fn sign_up_limit(requests: u32) -> TestApp {
+    TestApp::with_rate_limit(RateLimitSettings {
+        enabled: true,
+        trusted_proxies: Vec::new(),
+        rules: vec![RateLimitRule {
+            method: Some("POST".to_string()),
+            route: "/v1/users".to_string(),
+            requests: requests.try_into().unwrap(),
+            period_secs: 60,
+            key: RateLimitKey::Ip,
+        }],
+    })
+}
+
+fn user(username: &str) -> Value {
+    json!({
+        "username": username,
+        "email": format!("{}@example.com", username.to_lowercase()),
+        "password": "Correct horse battery staple 1!",
+    })
+}
+
+#[tokio::test]
+async fn test_sign_up_limit() {
+    let app = sign_up_limit(2);
+
+    let response = app.post_json("/v1/users", &user("HelloWorldIAmTim")).await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    assert_eq!(header(&response, "ratelimit-limit"), "2");
+    assert_eq!(header(&response, "ratelimit-remaining"), "1");
+    assert_eq!(header(&response, "ratelimit-policy"), "2;w=60");
+    assert!(!response.headers.contains_key(RETRY_AFTER));
+    let response = app.post_json("/v1/users", &user("HelloWorldIAmTom")).await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    assert_eq!(header(&response, "ratelimit-remaining"), "0");
+
+    let response = app.post_json("/v1/users", &user("HelloWorldIAmAnn")).await;
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.body["code"], "rate_limited");
+    // a token refills every 30 seconds, minus the time hashing passwords took
+    let retry_after = seconds(&response, RETRY_AFTER.as_str());
+    assert!((1..=30).contains(&retry_after), "{retry_after}");
+    assert_eq!(header(&response, "ratelimit-limit"), "2");
+    assert_eq!(header(&response, "ratelimit-remaining"), "0");
+    let reset = seconds(&response, "ratelimit-reset");
+    assert!((31..=60).contains(&reset), "{reset}");
+    assert!(app
+        .users
+        .get_by_username("HelloWorldIAmAnn")
+        .await
+        .unwrap()
+        .is_none());
+
+    // other routes are not limited
+    let response = app
+        .post_json(
+            "/v1/auth/login",
+            &json!({
+                "username": "HelloWorldIAmTim",
+                "password": "Correct horse battery staple 1!",
+            }),
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert!(!response.headers.contains_key("ratelimit-limit"));
+}
+
+#[tokio::test]
+async fn test_alias_shares_bucket() {
+    let app = sign_up_limit(1);
+    let response = app
+        .post_json("/user/create", &user("HelloWorldIAmTim"))
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    assert_eq!(header(&response, "ratelimit-policy"), "1;w=60");
+    let response = app.post_json("/v1/users", &user("HelloWorldIAmTom")).await;
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+    let response = app.post_json("/users", &user("HelloWorldIAmTom")).await;
+    assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+}
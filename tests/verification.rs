@@ -0,0 +1,63 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::TestApp;
+use serde_json::json;
+
+/// The token of the latest verification mail.
+fn last_token(app: &TestApp) -> String {
+    let sent = app.mailer.sent.lock().unwrap();
+    let body = &sent.last().unwrap().body;
+    let rest = body.split("?token=").nth(1).unwrap();
+    rest.split_whitespace().next().unwrap().to_string()
+}
+
+async fn verify(app: &TestApp, token: &str) -> common::TestResponse {
+    app.send(
+        Method::GET,
+        &format!("/v1/user/verify?token={token}"),
+        None,
+        None,
+    )
+    .await
+}
+
+#[tokio::test]
+async fn test_link_works_once() {
+    let app = TestApp::new();
+    app.sign_up("HelloWorldIAmTim").await;
+    let token = last_token(&app);
+
+    let response = verify(&app, &token).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["email_verified"], true);
+
+    let response = verify(&app, &token).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(response.body["code"], "invalid_token");
+}
+
+#[tokio::test]
+async fn test_old_link_after_email_change() {
+    let app = TestApp::new();
+    let (id, access_token) = app.sign_up("HelloWorldIAmTim").await;
+    let first = last_token(&app);
+    let user = format!("/v1/users/{id}");
+    for email in ["other@example.com", "helloworldiamtim@example.com"] {
+        let response = app
+            .send(
+                Method::PATCH,
+                &user,
+                Some(&access_token),
+                Some(&json!({ "email": email })),
+            )
+            .await;
+        assert_eq!(response.status, StatusCode::OK);
+    }
+
+    // the first link names the current address again but was replaced
+    let response = verify(&app, &first).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    let response = verify(&app, &last_token(&app)).await;
+    assert_eq!(response.status, StatusCode::OK);
+}
@@ -0,0 +1,83 @@
+mod common;
+
+use axum::body::Body;
+use axum::http::header::{ACCEPT, CONTENT_TYPE, VARY};
+use axum::http::{Method, Request, StatusCode};
+use common::TestApp;
+use serde_json::json;
+
+fn create_user(uri: &str, accept: Option<&str>) -> Request<Body> {
+    let mut request = Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header(CONTENT_TYPE, "application/json");
+    if let Some(accept) = accept {
+        request = request.header(ACCEPT, accept);
+    }
+    let body = json!({
+        "username": "HelloWorldIAmTim",
+        "email": "tim@example.com",
+        "password": "Correct horse battery staple 1!",
+    });
+    request.body(Body::from(body.to_string())).unwrap()
+}
+
+#[tokio::test]
+async fn test_version_in_path() {
+    let app = TestApp::new();
+    let response = app.request(create_user("/v1/users", None)).await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    assert!(!response.headers.contains_key(VARY));
+
+    let response = app.request(create_user("/v2/users", None)).await;
+    assert_eq!(response.status, StatusCode::NOT_ACCEPTABLE);
+    assert_eq!(response.body["code"], "unsupported_version");
+}
+
+#[tokio::test]
+async fn test_version_in_accept() {
+    let app = TestApp::new();
+    let response = app
+        .request(create_user(
+            "/users",
+            Some("application/vnd.pirate.v1+json"),
+        ))
+        .await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    assert_eq!(response.headers[VARY], "accept");
+
+    let response = app
+        .request(create_user(
+            "/users",
+            Some("application/vnd.pirate.v7+json"),
+        ))
+        .await;
+    assert_eq!(response.status, StatusCode::NOT_ACCEPTABLE);
+    assert_eq!(response.body["message"], "API version 7 is not supported");
+}
+
+#[tokio::test]
+async fn test_latest_version_by_default() {
+    let app = TestApp::new();
+    let response = app.request(create_user("/users", None)).await;
+    assert_eq!(response.status, StatusCode::CREATED);
+
+    let (id, token) = app.sign_up("HelloWorldIAmTom").await;
+    let response = app
+        .send(
+            Method::GET,
+            &format!("/users/{id}?unused=1"),
+            Some(&token),
+            None,
+        )
+        .await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["username"], "HelloWorldIAmTom");
+
+    // unknown routes outside the API stay plain 404s
+    let request = Request::builder()
+        .uri("/nothing")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(app.request(request).await.status, StatusCode::NOT_FOUND);
+}